@@ -101,6 +101,126 @@ impl SetupData {
             _ => None,
         }
     }
+
+    /// If the `SetupData` represents a HID class request, return it
+    pub fn get_hid_class_request(self) -> Option<HIDRequest> {
+        match (
+            self.request_type.request_type(),
+            self.request_type.recipient(),
+        ) {
+            (RequestType::Class, Recipient::Interface) => match self.request_code {
+                0x01 => Some(HIDRequest::GetReport {
+                    report_type: HIDReportType::get((self.value >> 8) as u8),
+                    report_id: (self.value & 0xff) as u8,
+                    interface: self.index,
+                    requested_length: self.length,
+                }),
+                0x02 => Some(HIDRequest::GetIdle {
+                    report_id: (self.value & 0xff) as u8,
+                    interface: self.index,
+                }),
+                0x03 => Some(HIDRequest::GetProtocol {
+                    interface: self.index,
+                }),
+                0x09 => Some(HIDRequest::SetReport {
+                    report_type: HIDReportType::get((self.value >> 8) as u8),
+                    report_id: (self.value & 0xff) as u8,
+                    interface: self.index,
+                    report_length: self.length,
+                }),
+                0x0A => Some(HIDRequest::SetIdle {
+                    duration: (self.value >> 8) as u8,
+                    report_id: (self.value & 0xff) as u8,
+                    interface: self.index,
+                }),
+                0x0B => Some(HIDRequest::SetProtocol {
+                    protocol: (self.value & 0xff) as u8,
+                    interface: self.index,
+                }),
+                _ => None,
+            },
+            _ => None,
+        }
+    }
+
+    /// If the `SetupData` represents a mass storage class request, return it
+    pub fn get_msc_class_request(self) -> Option<MSCRequest> {
+        match (
+            self.request_type.request_type(),
+            self.request_type.recipient(),
+        ) {
+            (RequestType::Class, Recipient::Interface) => match self.request_code {
+                0xFE => Some(MSCRequest::GetMaxLun {
+                    interface: self.index,
+                }),
+                0xFF => Some(MSCRequest::BulkOnlyReset {
+                    interface: self.index,
+                }),
+                _ => None,
+            },
+            _ => None,
+        }
+    }
+}
+
+/// A class-specific request to a mass storage (bulk-only transport) interface
+#[derive(Debug)]
+pub enum MSCRequest {
+    GetMaxLun { interface: u16 },
+    BulkOnlyReset { interface: u16 },
+}
+
+/// A class-specific request to a HID interface
+#[derive(Debug)]
+pub enum HIDRequest {
+    GetReport {
+        report_type: HIDReportType,
+        report_id: u8,
+        interface: u16,
+        requested_length: u16,
+    },
+    GetIdle {
+        report_id: u8,
+        interface: u16,
+    },
+    GetProtocol {
+        interface: u16,
+    },
+    SetReport {
+        report_type: HIDReportType,
+        report_id: u8,
+        interface: u16,
+        report_length: u16,
+    },
+    SetIdle {
+        // In units of 4 milliseconds, or 0 for indefinite
+        duration: u8,
+        report_id: u8,
+        interface: u16,
+    },
+    SetProtocol {
+        protocol: u8,
+        interface: u16,
+    },
+}
+
+#[derive(Copy, Clone, Debug)]
+pub enum HIDReportType {
+    Input,
+    Output,
+    Feature,
+    Reserved,
+}
+
+impl HIDReportType {
+    fn get(byte: u8) -> Self {
+        match byte {
+            1 => HIDReportType::Input,
+            2 => HIDReportType::Output,
+            3 => HIDReportType::Feature,
+            _ => HIDReportType::Reserved,
+        }
+    }
 }
 
 #[derive(Debug)]
@@ -155,6 +275,7 @@ pub enum DescriptorType {
     InterfacePower,
     HID = 0x21,
     Report = 0x22,
+    SsEndpointCompanion = 0x30,
 }
 
 fn get_descriptor_type(byte: u8) -> Option<DescriptorType> {
@@ -421,6 +542,14 @@ impl ConfigurationAttributes {
                 | if supports_remote_wakeup { 1 << 5 } else { 0 },
         )
     }
+
+    pub fn is_self_powered(self) -> bool {
+        self.0 & (1 << 6) != 0
+    }
+
+    pub fn supports_remote_wakeup(self) -> bool {
+        self.0 & (1 << 5) != 0
+    }
 }
 
 impl From<ConfigurationAttributes> for u8 {
@@ -520,6 +649,32 @@ impl Descriptor for EndpointDescriptor {
     }
 }
 
+/// A SuperSpeed Endpoint Companion descriptor, which follows its endpoint descriptor
+pub struct SsEndpointCompanionDescriptor {
+    // Packets the endpoint can send or receive in a burst, minus one
+    pub max_burst: u8,
+    // Max streams for bulk endpoints, or the Mult field for isochronous endpoints
+    pub attributes: u8,
+    // Bytes per service interval, for periodic endpoints
+    pub bytes_per_interval: u16,
+}
+
+impl Descriptor for SsEndpointCompanionDescriptor {
+    fn size(&self) -> usize {
+        6
+    }
+
+    fn write_to_unchecked(&self, buf: &[Cell<u8>]) -> usize {
+        let len = self.size();
+        buf[0].set(len as u8);
+        buf[1].set(DescriptorType::SsEndpointCompanion as u8);
+        buf[2].set(self.max_burst);
+        buf[3].set(self.attributes);
+        put_u16(&buf[4..6], self.bytes_per_interval);
+        len
+    }
+}
+
 #[derive(Copy, Clone)]
 pub enum HIDCountryCode {
     NotSupported = 0,
@@ -608,6 +763,159 @@ impl Descriptor for ReportDescriptor<'a> {
     }
 }
 
+/// Assemble the bytes of a HID report descriptor from a list of named items
+///
+/// Each item takes a one-byte value by default; append `as u16` or `as u32` to
+/// encode a wider value.  A negative value is encoded in two's complement, and
+/// takes `as i16` or `as i32` instead, e.g. `logical_minimum(-300 as i16)`.  A
+/// value too large for its size fails to compile rather than being truncated,
+/// e.g. `report_count(300)` must be written `report_count(300 as u16)`.
+/// `push` and `pop` are written without a value, as they carry no data.
+/// Collections enclose their items in braces, so every collection is closed by
+/// construction.  An unknown item name fails to compile.
+///
+/// ```rust
+/// static REPORT: &[u8] = &hid_report! {
+///     usage_page(0xF1D0 as u16),
+///     usage(0x01),
+///     collection(application) {
+///         usage(0x20),
+///         logical_minimum(0x00),
+///         logical_maximum(0xFF as u16),
+///         report_size(8),
+///         report_count(64),
+///         input(0x02),
+///     },
+/// };
+/// ```
+#[macro_export]
+macro_rules! hid_report {
+    (@tag input) => { 0x80 };
+    (@tag output) => { 0x90 };
+    (@tag feature) => { 0xB0 };
+    (@tag usage_page) => { 0x04 };
+    (@tag logical_minimum) => { 0x14 };
+    (@tag logical_maximum) => { 0x24 };
+    (@tag physical_minimum) => { 0x34 };
+    (@tag physical_maximum) => { 0x44 };
+    (@tag unit_exponent) => { 0x54 };
+    (@tag unit) => { 0x64 };
+    (@tag report_size) => { 0x74 };
+    (@tag report_id) => { 0x84 };
+    (@tag report_count) => { 0x94 };
+    (@tag usage) => { 0x08 };
+    (@tag usage_minimum) => { 0x18 };
+    (@tag usage_maximum) => { 0x28 };
+
+    (@collection physical) => { 0x00 };
+    (@collection application) => { 0x01 };
+    (@collection logical) => { 0x02 };
+    (@collection report) => { 0x03 };
+    (@collection named_array) => { 0x04 };
+    (@collection usage_switch) => { 0x05 };
+    (@collection usage_modifier) => { 0x06 };
+
+    // Going through a constant of the item's size makes an oversized literal an error
+    (@byte $size:ty, - $value:tt, $shift:expr) => {{
+        const VALUE: $size = -$value;
+        (VALUE >> $shift) as u8
+    }};
+    (@byte $size:ty, $value:tt, $shift:expr) => {{
+        const VALUE: $size = $value;
+        (VALUE >> $shift) as u8
+    }};
+
+    (@items [$($out:tt)*]) => {
+        [$($out)*]
+    };
+    (@items [$($out:tt)*] , $($rest:tt)*) => {
+        $crate::hid_report!(@items [$($out)*] $($rest)*)
+    };
+    // Items without data
+    (@items [$($out:tt)*] @end_collection $($rest:tt)*) => {
+        $crate::hid_report!(@items [$($out)* 0xC0u8,] $($rest)*)
+    };
+    (@items [$($out:tt)*] push($($value:tt)*) $($rest:tt)*) => {
+        compile_error!("push takes no value")
+    };
+    (@items [$($out:tt)*] pop($($value:tt)*) $($rest:tt)*) => {
+        compile_error!("pop takes no value")
+    };
+    (@items [$($out:tt)*] push $($rest:tt)*) => {
+        $crate::hid_report!(@items [$($out)* 0xA4u8,] $($rest)*)
+    };
+    (@items [$($out:tt)*] pop $($rest:tt)*) => {
+        $crate::hid_report!(@items [$($out)* 0xB4u8,] $($rest)*)
+    };
+    (@items [$($out:tt)*] collection($kind:ident) { $($inner:tt)* } $($rest:tt)*) => {
+        $crate::hid_report!(
+            @items [$($out)* 0xA1u8, $crate::hid_report!(@collection $kind),]
+            $($inner)* @end_collection $($rest)*
+        )
+    };
+    // Negative values, in two's complement
+    (@items [$($out:tt)*] $item:ident(- $value:tt as i32) $($rest:tt)*) => {
+        $crate::hid_report!(
+            @items [$($out)*
+                $crate::hid_report!(@tag $item) | 3u8,
+                $crate::hid_report!(@byte i32, -$value, 0),
+                $crate::hid_report!(@byte i32, -$value, 8),
+                $crate::hid_report!(@byte i32, -$value, 16),
+                $crate::hid_report!(@byte i32, -$value, 24),
+            ]
+            $($rest)*
+        )
+    };
+    (@items [$($out:tt)*] $item:ident(- $value:tt as i16) $($rest:tt)*) => {
+        $crate::hid_report!(
+            @items [$($out)*
+                $crate::hid_report!(@tag $item) | 2u8,
+                $crate::hid_report!(@byte i16, -$value, 0),
+                $crate::hid_report!(@byte i16, -$value, 8),
+            ]
+            $($rest)*
+        )
+    };
+    (@items [$($out:tt)*] $item:ident(- $value:tt) $($rest:tt)*) => {
+        $crate::hid_report!(
+            @items [$($out)* $crate::hid_report!(@tag $item) | 1u8, $crate::hid_report!(@byte i8, -$value, 0),]
+            $($rest)*
+        )
+    };
+    (@items [$($out:tt)*] $item:ident($value:tt as u32) $($rest:tt)*) => {
+        $crate::hid_report!(
+            @items [$($out)*
+                $crate::hid_report!(@tag $item) | 3u8,
+                $crate::hid_report!(@byte u32, $value, 0),
+                $crate::hid_report!(@byte u32, $value, 8),
+                $crate::hid_report!(@byte u32, $value, 16),
+                $crate::hid_report!(@byte u32, $value, 24),
+            ]
+            $($rest)*
+        )
+    };
+    (@items [$($out:tt)*] $item:ident($value:tt as u16) $($rest:tt)*) => {
+        $crate::hid_report!(
+            @items [$($out)*
+                $crate::hid_report!(@tag $item) | 2u8,
+                $crate::hid_report!(@byte u16, $value, 0),
+                $crate::hid_report!(@byte u16, $value, 8),
+            ]
+            $($rest)*
+        )
+    };
+    (@items [$($out:tt)*] $item:ident($value:tt) $($rest:tt)*) => {
+        $crate::hid_report!(
+            @items [$($out)* $crate::hid_report!(@tag $item) | 1u8, $crate::hid_report!(@byte u8, $value, 0),]
+            $($rest)*
+        )
+    };
+
+    ($($items:tt)*) => {
+        $crate::hid_report!(@items [] $($items)*)
+    };
+}
+
 pub struct LanguagesDescriptor<'a> {
     pub langs: &'a [u16],
 }
@@ -667,3 +975,144 @@ fn put_u16<'a>(buf: &'a [Cell<u8>], n: u16) {
     buf[0].set((n & 0xff) as u8);
     buf[1].set((n >> 8) as u8);
 }
+
+#[cfg(test)]
+mod test {
+    #[test]
+    pub fn hid_report_matches_fido_descriptor() {
+        static FIDO_REPORT: &[u8] = &[
+            0x06, 0xD0, 0xF1, // Usage Page (FIDO Alliance)
+            0x09, 0x01, // Usage (U2F Authenticator Device)
+            0xA1, 0x01, // Collection (Application)
+            0x09, 0x20, // Usage (Input Report Data)
+            0x15, 0x00, // Logical Minimum (0)
+            0x26, 0xFF, 0x00, // Logical Maximum (255)
+            0x75, 0x08, // Report Size (8)
+            0x95, 0x40, // Report Count (64)
+            0x81, 0x02, // Input (Data, Variable, Absolute)
+            0x09, 0x21, // Usage (Output Report Data)
+            0x15, 0x00, // Logical Minimum (0)
+            0x26, 0xFF, 0x00, // Logical Maximum (255)
+            0x75, 0x08, // Report Size (8)
+            0x95, 0x40, // Report Count (64)
+            0x91, 0x02, // Output (Data, Variable, Absolute)
+            0xC0, // End Collection
+        ];
+
+        let report: &[u8] = &hid_report! {
+            usage_page(0xF1D0 as u16),
+            usage(0x01),
+            collection(application) {
+                usage(0x20),
+                logical_minimum(0x00),
+                logical_maximum(0xFF as u16),
+                report_size(8),
+                report_count(64),
+                input(0x02),
+                usage(0x21),
+                logical_minimum(0x00),
+                logical_maximum(0xFF as u16),
+                report_size(8),
+                report_count(64),
+                output(0x02),
+            },
+        };
+        assert_eq!(report, FIDO_REPORT);
+    }
+
+    #[test]
+    pub fn hid_report_value_sizes() {
+        // The largest one-byte value, and a larger one given its own size
+        let report: &[u8] = &hid_report! {
+            report_count(255),
+            report_count(300 as u16),
+            report_count(70_000 as u32),
+        };
+        assert_eq!(
+            report,
+            &[0x95, 0xFF, 0x96, 0x2C, 0x01, 0x97, 0x70, 0x11, 0x01, 0x00][..]
+        );
+    }
+
+    #[test]
+    pub fn hid_report_signed_values() {
+        // The smallest value of each size, then ones that need a wider size
+        let report: &[u8] = &hid_report! {
+            logical_minimum(-128),
+            logical_minimum(-129 as i16),
+            logical_minimum(-32_768 as i16),
+            logical_minimum(-40_000 as i32),
+            logical_maximum(127),
+        };
+        assert_eq!(
+            report,
+            &[
+                0x15, 0x80, 0x16, 0x7F, 0xFF, 0x16, 0x00, 0x80, 0x17, 0xC0, 0x63, 0xFF, 0xFF, 0x25,
+                0x7F
+            ][..]
+        );
+    }
+
+    #[test]
+    pub fn hid_report_zero_data_items() {
+        let report: &[u8] = &hid_report! {
+            push,
+            report_size(8),
+            pop
+            collection(logical) {}
+            push
+            pop,
+        };
+        assert_eq!(
+            report,
+            &[0xA4, 0x75, 0x08, 0xB4, 0xA1, 0x02, 0xC0, 0xA4, 0xB4][..]
+        );
+    }
+
+    #[test]
+    pub fn hid_report_nested_collections() {
+        let report: &[u8] = &hid_report! {
+            usage_page(0x01),
+            collection(application) {
+                collection(physical) {
+                    usage(0x30)
+                    usage(0x31)
+                }
+                unit(0x1234_5678 as u32)
+            }
+        };
+        assert_eq!(
+            report,
+            &[
+                0x05, 0x01, 0xA1, 0x01, 0xA1, 0x00, 0x09, 0x30, 0x09, 0x31, 0xC0, 0x67, 0x78, 0x56,
+                0x34, 0x12, 0xC0
+            ][..]
+        );
+    }
+
+    #[test]
+    pub fn endpoint_address_encoding() {
+        use super::{EndpointAddress, TransferDirection};
+
+        for endpoint in 0..16 {
+            let expected_in = 0x80 | endpoint as u8;
+            let expected_out = endpoint as u8;
+            assert_eq!(
+                EndpointAddress::new_const(endpoint, TransferDirection::DeviceToHost).0,
+                expected_in
+            );
+            assert_eq!(
+                EndpointAddress::new(endpoint, TransferDirection::DeviceToHost).0,
+                expected_in
+            );
+            assert_eq!(
+                EndpointAddress::new_const(endpoint, TransferDirection::HostToDevice).0,
+                expected_out
+            );
+            assert_eq!(
+                EndpointAddress::new(endpoint, TransferDirection::HostToDevice).0,
+                expected_out
+            );
+        }
+    }
+}
@@ -0,0 +1,190 @@
+//! A USB controller, an alarm and a logger that record how clients drive
+//! them, for testing
+
+extern crate std;
+
+use super::usbc_client_ctrl::Logger;
+use core::cell::{Cell, RefCell};
+use core::fmt::{self, Write};
+use core::marker::PhantomData;
+use kernel::common::cells::{OptionalCell, VolatileCell};
+use kernel::hil;
+use kernel::hil::usb::{DeviceSpeed, TransferType};
+use std::string::String;
+
+const N_ENDPOINTS: usize = 4;
+
+pub struct MockController<'a> {
+    /// The buffer the client set for each endpoint
+    buffers: [OptionalCell<&'a [VolatileCell<u8>]>; N_ENDPOINTS],
+
+    /// The speed the device was enabled at, if it has been enabled
+    pub speed: Cell<Option<DeviceSpeed>>,
+
+    /// Whether the device is attached to the bus
+    pub attached: Cell<bool>,
+
+    /// Number of `endpoint_resume_in` calls
+    pub in_resumed: Cell<usize>,
+
+    /// Number of `endpoint_resume_out` calls
+    pub out_resumed: Cell<usize>,
+}
+
+impl<'a> MockController<'a> {
+    pub fn new() -> Self {
+        MockController {
+            buffers: [
+                OptionalCell::empty(),
+                OptionalCell::empty(),
+                OptionalCell::empty(),
+                OptionalCell::empty(),
+            ],
+            speed: Cell::new(None),
+            attached: Cell::new(false),
+            in_resumed: Cell::new(0),
+            out_resumed: Cell::new(0),
+        }
+    }
+
+    /// The buffer the client set for `endpoint`
+    pub fn buffer(&self, endpoint: usize) -> &'a [VolatileCell<u8>] {
+        self.buffers[endpoint].expect("No buffer set for this endpoint")
+    }
+
+    /// Receive a SETUP packet on the control endpoint, as the hardware would
+    pub fn setup(&self, packet: [u8; 8]) {
+        let buf = self.buffer(0);
+        for (i, b) in packet.iter().enumerate() {
+            buf[i].set(*b);
+        }
+    }
+}
+
+impl hil::usb::UsbController<'a> for MockController<'a> {
+    fn endpoint_set_buffer(&self, endpoint: usize, buf: &'a [VolatileCell<u8>]) {
+        self.buffers[endpoint].set(buf);
+    }
+
+    fn enable_as_device(&self, speed: DeviceSpeed) {
+        self.speed.set(Some(speed));
+    }
+
+    fn attach(&self) {
+        self.attached.set(true);
+    }
+
+    fn detach(&self) {
+        self.attached.set(false);
+    }
+
+    fn set_address(&self, _addr: u16) {}
+    fn enable_address(&self) {}
+    fn endpoint_in_enable(&self, _transfer_type: TransferType, _endpoint: usize) {}
+    fn endpoint_out_enable(&self, _transfer_type: TransferType, _endpoint: usize) {}
+    fn endpoint_in_out_enable(&self, _transfer_type: TransferType, _endpoint: usize) {}
+
+    fn endpoint_resume_in(&self, _endpoint: usize) {
+        self.in_resumed.set(self.in_resumed.get() + 1);
+    }
+
+    fn endpoint_resume_out(&self, _endpoint: usize) {
+        self.out_resumed.set(self.out_resumed.get() + 1);
+    }
+}
+
+/// An alarm that only fires when a test tells it to, ticking at 1 kHz unless
+/// given another frequency
+pub struct MockAlarm<'a, F = hil::time::Freq1KHz> {
+    /// The current time, in tics
+    pub now: Cell<u32>,
+
+    /// The time the alarm is set for, if it is enabled
+    pub alarm: Cell<Option<u32>>,
+
+    client: OptionalCell<&'a dyn hil::time::AlarmClient>,
+
+    frequency: PhantomData<F>,
+}
+
+impl<'a> MockAlarm<'a> {
+    pub fn new() -> Self {
+        MockAlarm::with_frequency()
+    }
+}
+
+impl<'a, F: hil::time::Frequency> MockAlarm<'a, F> {
+    pub fn with_frequency() -> Self {
+        MockAlarm {
+            now: Cell::new(0),
+            alarm: Cell::new(None),
+            client: OptionalCell::empty(),
+            frequency: PhantomData,
+        }
+    }
+
+    /// Advance time to the alarm and fire it
+    pub fn fire(&self) {
+        let alarm = self.alarm.take().expect("Alarm is not set");
+        self.now.set(alarm);
+        self.client.map(|client| client.fired());
+    }
+}
+
+impl<F: hil::time::Frequency> hil::time::Time for MockAlarm<'a, F> {
+    type Frequency = F;
+
+    fn now(&self) -> u32 {
+        self.now.get()
+    }
+
+    fn max_tics(&self) -> u32 {
+        core::u32::MAX
+    }
+}
+
+impl<F: hil::time::Frequency> hil::time::Alarm<'a> for MockAlarm<'a, F> {
+    fn set_alarm(&self, tics: u32) {
+        self.alarm.set(Some(tics));
+    }
+
+    fn get_alarm(&self) -> u32 {
+        self.alarm.get().unwrap_or(0)
+    }
+
+    fn set_client(&'a self, client: &'a dyn hil::time::AlarmClient) {
+        self.client.set(client);
+    }
+
+    fn is_enabled(&self) -> bool {
+        self.alarm.get().is_some()
+    }
+
+    fn disable(&self) {
+        self.alarm.set(None);
+    }
+}
+
+/// A logger that keeps every line it is given
+pub struct MockLogger {
+    output: RefCell<String>,
+}
+
+impl MockLogger {
+    pub fn new() -> Self {
+        MockLogger {
+            output: RefCell::new(String::new()),
+        }
+    }
+
+    /// Take what was logged since the last call, one line per message
+    pub fn take(&self) -> String {
+        self.output.replace(String::new())
+    }
+}
+
+impl Logger for MockLogger {
+    fn log(&self, args: fmt::Arguments) {
+        writeln!(self.output.borrow_mut(), "{}", args).unwrap();
+    }
+}
@@ -1,4 +1,13 @@
+#[macro_use]
 pub mod descriptors;
+#[cfg(test)]
+mod mock;
 pub mod usb_user;
+pub mod usbc_bulk_echo;
 pub mod usbc_client;
 pub mod usbc_client_ctrl;
+pub mod usbc_hid;
+pub mod usbc_hid_consumer;
+pub mod usbc_hid_gamepad;
+pub mod usbc_msc;
+pub mod usbc_reenumerate;
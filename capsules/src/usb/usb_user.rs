@@ -71,11 +71,11 @@ where
                         Request::EnableAndAttach => {
                             // Enable and attach (synchronously)
                             self.usbc_client.enable();
-                            self.usbc_client.attach();
+                            let result = self.usbc_client.attach();
 
                             // Schedule a callback immediately
                             if let Some(mut callback) = app.callback {
-                                callback.schedule(From::from(ReturnCode::SUCCESS), 0, 0);
+                                callback.schedule(From::from(result), 0, 0);
                             }
                             app.awaiting = None;
                         }
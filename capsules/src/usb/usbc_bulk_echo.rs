@@ -0,0 +1,440 @@
+//! A vendor-specific USB device that echoes bulk data, for throughput testing
+//!
+//! Every packet received on the bulk OUT endpoint 2 is sent back unchanged on
+//! the bulk IN endpoint 1, including zero-length packets.  While a packet is
+//! waiting to be echoed, further OUT packets are NAKed.  The capsule counts
+//! the bytes moved in each direction, so that a host tool can compare them
+//! with its own accounting.
+//!
+//! Both endpoints have a max packet size of 64 bytes, the largest a bulk
+//! endpoint may have at full speed.  Larger packets need a high-speed
+//! controller, and the `UsbController` HIL only offers full and low speed.
+//!
+//! Usage
+//! -----
+//!
+//! ```rust
+//! let echo = static_init!(
+//!     capsules::usb::usbc_bulk_echo::BulkEcho<'static, nrf52::usbd::Usbd<'static>>,
+//!     capsules::usb::usbc_bulk_echo::BulkEcho::new(&nrf52::usbd::USBD));
+//! nrf52::usbd::USBD.set_client(echo);
+//!
+//! echo.enable();
+//! echo.attach();
+//! ```
+
+use super::descriptors::Buffer64;
+use super::descriptors::DeviceDescriptor;
+use super::descriptors::EndpointAddress;
+use super::descriptors::EndpointDescriptor;
+use super::descriptors::TransferDirection;
+use super::usbc_client_ctrl::ClientCtrl;
+use super::usbc_client_ctrl::ClientCtrlBuilder;
+use super::usbc_client_ctrl::Logger;
+use core::cell::Cell;
+use kernel::hil;
+use kernel::hil::usb::TransferType;
+use kernel::ReturnCode;
+
+const VENDOR_ID: u16 = 0x6667;
+const PRODUCT_ID: u16 = 0xabcf;
+
+static STRINGS: &[&str] = &[
+    "XYZ Corp.",    // Manufacturer
+    "Bulk Echo",    // Product
+    "Serial No. 5", // Serial number
+];
+
+const IN_ENDPOINT: usize = 1;
+const OUT_ENDPOINT: usize = 2;
+
+/// Max packet size of both bulk endpoints; the largest allowed at full speed,
+/// which is the fastest our controllers run at
+const MAX_PACKET_SIZE: usize = 64;
+
+static ENDPOINTS: &[EndpointDescriptor] = &[
+    EndpointDescriptor {
+        endpoint_address: EndpointAddress::new_const(IN_ENDPOINT, TransferDirection::DeviceToHost),
+        transfer_type: TransferType::Bulk,
+        max_packet_size: MAX_PACKET_SIZE as u16,
+        interval: 0,
+    },
+    EndpointDescriptor {
+        endpoint_address: EndpointAddress::new_const(OUT_ENDPOINT, TransferDirection::HostToDevice),
+        transfer_type: TransferType::Bulk,
+        max_packet_size: MAX_PACKET_SIZE as u16,
+        interval: 0,
+    },
+];
+
+pub struct BulkEcho<'a, C: 'a> {
+    client_ctrl: ClientCtrl<'a, 'static, C>,
+
+    // Buffers for the bulk IN and OUT endpoints
+    in_buffer: Buffer64,
+    out_buffer: Buffer64,
+
+    // The length of the packet waiting in `in_buffer` to be echoed, if any
+    in_pending: Cell<Option<usize>>,
+
+    // Whether we NAKed an OUT packet because `in_buffer` was full
+    delayed_out: Cell<bool>,
+
+    // Bytes accepted on the OUT endpoint, and handed to the controller for the IN endpoint
+    bytes_received: Cell<usize>,
+    bytes_queued: Cell<usize>,
+
+    // Bytes the controller reported transmitted on the IN endpoint, once it has reported any
+    bytes_transmitted: Cell<Option<usize>>,
+}
+
+impl<'a, C: hil::usb::UsbController<'a>> BulkEcho<'a, C> {
+    pub fn new(controller: &'a C) -> Self {
+        BulkEcho {
+            client_ctrl: ClientCtrlBuilder::new(controller)
+                .device_descriptor(DeviceDescriptor {
+                    vendor_id: VENDOR_ID,
+                    product_id: PRODUCT_ID,
+                    manufacturer_string: 1,
+                    product_string: 2,
+                    serial_number_string: 3,
+                    max_packet_size_ep0: 64,
+                    ..Default::default()
+                })
+                .endpoint_descriptors(ENDPOINTS)
+                .strings(STRINGS)
+                .build(),
+            in_buffer: Default::default(),
+            out_buffer: Default::default(),
+            in_pending: Cell::new(None),
+            delayed_out: Cell::new(false),
+            bytes_received: Cell::new(0),
+            bytes_queued: Cell::new(0),
+            bytes_transmitted: Cell::new(None),
+        }
+    }
+
+    /// Number of bytes received from the host since the last bus reset
+    pub fn bytes_received(&self) -> usize {
+        self.bytes_received.get()
+    }
+
+    /// Number of bytes echoed back to the host since the last bus reset
+    ///
+    /// Once the controller has reported a transmitted packet, these are the
+    /// bytes it reported.  Controllers that never report transmits, like the
+    /// SAM4L's, leave us to count the bytes handed to them instead.
+    pub fn bytes_sent(&self) -> usize {
+        self.bytes_transmitted
+            .get()
+            .unwrap_or_else(|| self.bytes_queued.get())
+    }
+
+    /// Register a logger to receive our diagnostics instead of `debug!`
+    pub fn set_logger(&self, logger: &'a dyn Logger) {
+        self.client_ctrl.set_logger(logger);
+    }
+
+    #[inline]
+    fn controller(&'a self) -> &'a C {
+        self.client_ctrl.controller()
+    }
+}
+
+impl<'a, C: hil::usb::UsbController<'a>> hil::usb::Client<'a> for BulkEcho<'a, C> {
+    fn enable(&'a self) {
+        // Set up the default control endpoint
+        self.client_ctrl.enable();
+
+        self.controller()
+            .endpoint_set_buffer(IN_ENDPOINT, &self.in_buffer.buf);
+        self.controller()
+            .endpoint_in_enable(TransferType::Bulk, IN_ENDPOINT);
+
+        self.controller()
+            .endpoint_set_buffer(OUT_ENDPOINT, &self.out_buffer.buf);
+        self.controller()
+            .endpoint_out_enable(TransferType::Bulk, OUT_ENDPOINT);
+    }
+
+    fn attach(&'a self) -> ReturnCode {
+        self.client_ctrl.attach()
+    }
+
+    fn bus_reset(&'a self) {
+        self.client_ctrl.bus_reset();
+
+        self.in_pending.set(None);
+        self.delayed_out.set(false);
+        self.bytes_received.set(0);
+        self.bytes_queued.set(0);
+        // The controller keeps reporting transmits if it ever did
+        self.bytes_transmitted
+            .set(self.bytes_transmitted.get().map(|_| 0));
+    }
+
+    /// Handle a Control Setup transaction
+    fn ctrl_setup(&'a self, endpoint: usize) -> hil::usb::CtrlSetupResult {
+        self.client_ctrl.ctrl_setup(endpoint)
+    }
+
+    /// Handle a Control In transaction
+    fn ctrl_in(&'a self, endpoint: usize) -> hil::usb::CtrlInResult {
+        self.client_ctrl.ctrl_in(endpoint)
+    }
+
+    /// Handle a Control Out transaction
+    fn ctrl_out(&'a self, endpoint: usize, packet_bytes: u32) -> hil::usb::CtrlOutResult {
+        self.client_ctrl.ctrl_out(endpoint, packet_bytes)
+    }
+
+    fn ctrl_status(&'a self, endpoint: usize) {
+        self.client_ctrl.ctrl_status(endpoint)
+    }
+
+    /// Handle the completion of a Control transfer
+    fn ctrl_status_complete(&'a self, endpoint: usize) {
+        self.client_ctrl.ctrl_status_complete(endpoint)
+    }
+
+    /// Handle a Bulk/Interrupt IN transaction
+    fn packet_in(&'a self, transfer_type: TransferType, endpoint: usize) -> hil::usb::InResult {
+        match (transfer_type, endpoint) {
+            (TransferType::Bulk, IN_ENDPOINT) => match self.in_pending.take() {
+                Some(packet_bytes) => {
+                    // The IN buffer is free again, so we can accept more data
+                    if self.delayed_out.take() {
+                        self.controller().endpoint_resume_out(OUT_ENDPOINT);
+                    }
+                    self.bytes_queued
+                        .set(self.bytes_queued.get() + packet_bytes);
+                    hil::usb::InResult::Packet(packet_bytes)
+                }
+                None => hil::usb::InResult::Delay,
+            },
+            (TransferType::Bulk, _) => {
+                self.client_ctrl.log(format_args!(
+                    "bulk_echo: packet_in on unknown endpoint {}",
+                    endpoint
+                ));
+                hil::usb::InResult::Error
+            }
+            (_, _) => {
+                self.client_ctrl.log(format_args!(
+                    "bulk_echo: packet_in({}) is not a bulk transfer",
+                    endpoint
+                ));
+                hil::usb::InResult::Error
+            }
+        }
+    }
+
+    /// Handle a Bulk/Interrupt OUT transaction
+    fn packet_out(
+        &'a self,
+        transfer_type: TransferType,
+        endpoint: usize,
+        packet_bytes: u32,
+    ) -> hil::usb::OutResult {
+        match (transfer_type, endpoint) {
+            (TransferType::Bulk, OUT_ENDPOINT) => {
+                let packet_bytes = packet_bytes as usize;
+                if packet_bytes > MAX_PACKET_SIZE {
+                    self.client_ctrl.log(format_args!(
+                        "bulk_echo: OUT packet of {} bytes is too long",
+                        packet_bytes
+                    ));
+                    return hil::usb::OutResult::Error;
+                }
+                if self.in_pending.get().is_some() {
+                    // The previous packet has not been echoed yet
+                    self.delayed_out.set(true);
+                    return hil::usb::OutResult::Delay;
+                }
+
+                for i in 0..packet_bytes {
+                    self.in_buffer.buf[i].set(self.out_buffer.buf[i].get());
+                }
+                self.in_pending.set(Some(packet_bytes));
+                self.bytes_received
+                    .set(self.bytes_received.get() + packet_bytes);
+
+                // Alert the controller that we now have data to send
+                self.controller().endpoint_resume_in(IN_ENDPOINT);
+                hil::usb::OutResult::Ok
+            }
+            (TransferType::Bulk, _) => {
+                self.client_ctrl.log(format_args!(
+                    "bulk_echo: packet_out on unknown endpoint {}",
+                    endpoint
+                ));
+                hil::usb::OutResult::Error
+            }
+            (_, _) => {
+                self.client_ctrl.log(format_args!(
+                    "bulk_echo: packet_out({}) is not a bulk transfer",
+                    endpoint
+                ));
+                hil::usb::OutResult::Error
+            }
+        }
+    }
+
+    fn packet_transmitted(&'a self, endpoint: usize, packet_bytes: usize) {
+        // Count what the host actually received, rather than what we queued
+        if endpoint == IN_ENDPOINT {
+            self.bytes_transmitted.set(Some(
+                self.bytes_transmitted.get().unwrap_or(0) + packet_bytes,
+            ));
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::super::mock::{MockController, MockLogger};
+    use super::{BulkEcho, IN_ENDPOINT, MAX_PACKET_SIZE, OUT_ENDPOINT};
+    use kernel::hil::usb::{Client, InResult, OutResult, TransferType};
+
+    /// Send a packet of `len` bytes to the echo, and check it comes back
+    fn echo_packet(
+        echo: &'a BulkEcho<'a, MockController<'a>>,
+        controller: &MockController<'a>,
+        len: usize,
+    ) {
+        for (i, b) in controller.buffer(OUT_ENDPOINT).iter().take(len).enumerate() {
+            b.set(i as u8 ^ len as u8);
+        }
+        assert!(
+            match echo.packet_out(TransferType::Bulk, OUT_ENDPOINT, len as u32) {
+                OutResult::Ok => true,
+                _ => false,
+            }
+        );
+
+        // A second packet must wait for the first one to be echoed
+        assert!(match echo.packet_out(TransferType::Bulk, OUT_ENDPOINT, 1) {
+            OutResult::Delay => true,
+            _ => false,
+        });
+
+        let resumed_out = controller.out_resumed.get();
+        assert!(match echo.packet_in(TransferType::Bulk, IN_ENDPOINT) {
+            InResult::Packet(n) => n == len,
+            _ => false,
+        });
+        assert_eq!(controller.out_resumed.get(), resumed_out + 1);
+
+        for (i, b) in controller.buffer(IN_ENDPOINT).iter().take(len).enumerate() {
+            assert_eq!(b.get(), i as u8 ^ len as u8);
+        }
+    }
+
+    #[test]
+    pub fn echoes_burst() {
+        let controller = MockController::new();
+        let echo = BulkEcho::new(&controller);
+        echo.enable();
+
+        // Until the controller reports a transmit, packets count as sent once handed over
+        echo_packet(&echo, &controller, 64);
+        assert_eq!(echo.bytes_sent(), 64);
+        echo.packet_transmitted(IN_ENDPOINT, 64);
+        assert_eq!(echo.bytes_sent(), 64);
+
+        let mut expected = 64;
+        for len in &[64, 17, 0] {
+            echo_packet(&echo, &controller, *len);
+
+            // From then on, only a packet the controller has transmitted counts as sent
+            assert_eq!(echo.bytes_sent(), expected);
+            echo.packet_transmitted(IN_ENDPOINT, *len);
+            assert_eq!(echo.bytes_sent(), expected + len);
+            expected += len;
+        }
+
+        assert!(match echo.packet_in(TransferType::Bulk, IN_ENDPOINT) {
+            InResult::Delay => true,
+            _ => false,
+        });
+        assert_eq!(echo.bytes_received(), expected);
+        assert_eq!(echo.bytes_sent(), expected);
+
+        // Transmits are still reported after a bus reset
+        echo.bus_reset();
+        echo_packet(&echo, &controller, 8);
+        assert_eq!(echo.bytes_sent(), 0);
+    }
+
+    #[test]
+    pub fn counts_sent_without_transmit_reports() {
+        let controller = MockController::new();
+        let echo = BulkEcho::new(&controller);
+        echo.enable();
+
+        // Like the SAM4L's, this controller never calls `packet_transmitted`
+        let mut expected = 0;
+        for len in &[64, 64, 17, 0] {
+            echo_packet(&echo, &controller, *len);
+            expected += len;
+            assert_eq!(echo.bytes_sent(), expected);
+        }
+        assert_eq!(echo.bytes_received(), expected);
+    }
+
+    #[test]
+    pub fn logs_errors() {
+        let log = MockLogger::new();
+        let controller = MockController::new();
+        let echo = BulkEcho::new(&controller);
+        echo.set_logger(&log);
+        echo.enable();
+
+        assert!(match echo.packet_in(TransferType::Bulk, OUT_ENDPOINT) {
+            InResult::Error => true,
+            _ => false,
+        });
+        assert_eq!(log.take(), "bulk_echo: packet_in on unknown endpoint 2\n");
+
+        assert!(match echo.packet_in(TransferType::Interrupt, IN_ENDPOINT) {
+            InResult::Error => true,
+            _ => false,
+        });
+        assert_eq!(
+            log.take(),
+            "bulk_echo: packet_in(1) is not a bulk transfer\n"
+        );
+
+        assert!(
+            match echo.packet_out(TransferType::Bulk, OUT_ENDPOINT, MAX_PACKET_SIZE as u32 + 1) {
+                OutResult::Error => true,
+                _ => false,
+            }
+        );
+        assert_eq!(
+            log.take(),
+            "bulk_echo: OUT packet of 65 bytes is too long\n"
+        );
+
+        assert!(match echo.packet_out(TransferType::Bulk, IN_ENDPOINT, 1) {
+            OutResult::Error => true,
+            _ => false,
+        });
+        assert_eq!(log.take(), "bulk_echo: packet_out on unknown endpoint 1\n");
+
+        assert!(
+            match echo.packet_out(TransferType::Interrupt, OUT_ENDPOINT, 1) {
+                OutResult::Error => true,
+                _ => false,
+            }
+        );
+        assert_eq!(
+            log.take(),
+            "bulk_echo: packet_out(2) is not a bulk transfer\n"
+        );
+
+        // None of this reached the echo
+        assert_eq!(echo.bytes_received(), 0);
+        assert_eq!(echo.bytes_sent(), 0);
+    }
+}
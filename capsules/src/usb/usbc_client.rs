@@ -1,6 +1,16 @@
 //! A bare-bones client of the USB hardware interface
 //!
 //! It responds to standard device requests and can be enumerated.
+//!
+//! The serial number string can be set for each image at build time through
+//! the `USB_SERIAL_NUMBER` environment variable:
+//!
+//! ```shell
+//! $ USB_SERIAL_NUMBER=0123456789 make
+//! ```
+//!
+//! The capsules are rebuilt whenever this variable changes.  Setting it to the
+//! empty string builds a device without a serial number.
 
 use super::descriptors::Buffer8;
 use super::descriptors::DeviceDescriptor;
@@ -8,11 +18,13 @@ use super::descriptors::EndpointAddress;
 use super::descriptors::EndpointDescriptor;
 use super::descriptors::TransferDirection;
 use super::usbc_client_ctrl::ClientCtrl;
+use super::usbc_client_ctrl::ClientCtrlBuilder;
 use core::cell::Cell;
 use kernel::common::cells::VolatileCell;
 use kernel::debug;
 use kernel::hil;
 use kernel::hil::usb::TransferType;
+use kernel::ReturnCode;
 
 const VENDOR_ID: u16 = 0x6667;
 const PRODUCT_ID: u16 = 0xabcd;
@@ -24,9 +36,11 @@ static LANGUAGES: &'static [u16; 1] = &[
 static STRINGS: &'static [&'static str] = &[
     "XYZ Corp.",      // Manufacturer
     "The Zorpinator", // Product
-    "Serial No. 5",   // Serial number
 ];
 
+/// The serial number, unless one is given at build time
+const DEFAULT_SERIAL_NUMBER: &str = "Serial No. 5";
+
 const N_ENDPOINTS: usize = 2;
 
 static ENDPOINTS: &'static [EndpointDescriptor; N_ENDPOINTS] = &[
@@ -58,26 +72,30 @@ pub struct Client<'a, C: 'a> {
 }
 
 impl<'a, C: hil::usb::UsbController<'a>> Client<'a, C> {
+    /// A client with the serial number given at build time, if any
     pub fn new(controller: &'a C) -> Self {
+        Client::with_serial_number(
+            controller,
+            option_env!("USB_SERIAL_NUMBER").unwrap_or(DEFAULT_SERIAL_NUMBER),
+        )
+    }
+
+    /// A client with the given serial number, or none if it is empty
+    pub fn with_serial_number(controller: &'a C, serial_number: &'static str) -> Self {
         Client {
-            client_ctrl: ClientCtrl::new(
-                controller,
-                DeviceDescriptor {
+            client_ctrl: ClientCtrlBuilder::new(controller)
+                .device_descriptor(DeviceDescriptor {
                     vendor_id: VENDOR_ID,
                     product_id: PRODUCT_ID,
                     manufacturer_string: 1,
                     product_string: 2,
-                    serial_number_string: 3,
                     ..Default::default()
-                },
-                Default::default(),
-                Default::default(),
-                ENDPOINTS,
-                None, // No interface class descriptor
-                None, // No report descriptor
-                LANGUAGES,
-                STRINGS,
-            ),
+                })
+                .endpoint_descriptors(ENDPOINTS)
+                .language(LANGUAGES)
+                .strings(STRINGS)
+                .serial_number(serial_number)
+                .build(),
             buffers: Default::default(),
             echo_buf: Default::default(),
             echo_len: Cell::new(0),
@@ -85,6 +103,16 @@ impl<'a, C: hil::usb::UsbController<'a>> Client<'a, C> {
         }
     }
 
+    /// Print the state of the client on the console, for debugging
+    pub fn dump_state(&'a self) {
+        self.client_ctrl.dump_state();
+        debug!(
+            "usb_client: echo_len={} delayed_out={}",
+            self.echo_len.get(),
+            self.delayed_out.get()
+        );
+    }
+
     fn alert_full(&'a self) {
         // Alert the controller that we now have data to send on the Bulk IN endpoint 1
         self.controller().endpoint_resume_in(1);
@@ -123,8 +151,8 @@ impl<'a, C: hil::usb::UsbController<'a>> hil::usb::Client<'a> for Client<'a, C>
         self.controller().endpoint_out_enable(TransferType::Bulk, 2);
     }
 
-    fn attach(&'a self) {
-        self.client_ctrl.attach();
+    fn attach(&'a self) -> ReturnCode {
+        self.client_ctrl.attach()
     }
 
     fn bus_reset(&'a self) {
@@ -133,6 +161,8 @@ impl<'a, C: hil::usb::UsbController<'a>> hil::usb::Client<'a> for Client<'a, C>
 
         debug!("Bus reset");
 
+        self.client_ctrl.bus_reset();
+
         // Reset the state for our pair of debugging endpoints
         self.echo_len.set(0);
         self.delayed_out.set(false);
@@ -235,8 +265,73 @@ impl<'a, C: hil::usb::UsbController<'a>> hil::usb::Client<'a> for Client<'a, C>
             TransferType::Control | TransferType::Isochronous => unreachable!(),
         }
     }
+}
 
-    fn packet_transmitted(&'a self, _endpoint: usize) {
-        // Nothing to do.
+#[cfg(test)]
+mod test {
+    use super::super::mock::MockController;
+    use super::Client;
+    use kernel::hil::usb::{Client as _, CtrlInResult, CtrlSetupResult};
+
+    /// Read string descriptor 3, where the serial number is served
+    fn serial_number_descriptor(
+        client: &'a Client<'a, MockController<'a>>,
+        controller: &MockController<'a>,
+        descriptor: &mut [u8],
+    ) -> Result<usize, CtrlSetupResult> {
+        // GET_DESCRIPTOR(String 3, en-US)
+        controller.setup([0x80, 0x06, 0x03, 0x03, 0x09, 0x04, 0xff, 0x00]);
+        match client.ctrl_setup(0) {
+            CtrlSetupResult::Ok => {}
+            result => return Err(result),
+        }
+        let mut len = 0;
+        loop {
+            match client.ctrl_in(0) {
+                CtrlInResult::Packet(n, complete) => {
+                    for (d, b) in descriptor[len..len + n]
+                        .iter_mut()
+                        .zip(controller.buffer(0).iter())
+                    {
+                        *d = b.get();
+                    }
+                    len += n;
+                    if complete {
+                        return Ok(len);
+                    }
+                }
+                _ => panic!("control read failed"),
+            }
+        }
+    }
+
+    #[test]
+    pub fn serves_serial_number() {
+        let controller = MockController::new();
+        let client = Client::with_serial_number(&controller, "ABC123");
+        client.enable();
+
+        let mut descriptor = [0; 64];
+        let len = serial_number_descriptor(&client, &controller, &mut descriptor).ok();
+        assert_eq!(len, Some(14));
+        assert_eq!(
+            &descriptor[..14],
+            &[14, 0x03, b'A', 0, b'B', 0, b'C', 0, b'1', 0, b'2', 0, b'3', 0][..]
+        );
+    }
+
+    #[test]
+    pub fn empty_serial_number_is_not_served() {
+        let controller = MockController::new();
+        let client = Client::with_serial_number(&controller, "");
+        client.enable();
+
+        let mut descriptor = [0; 64];
+        assert!(
+            match serial_number_descriptor(&client, &controller, &mut descriptor) {
+                Err(CtrlSetupResult::ErrInvalidStringIndex) => true,
+                _ => false,
+            }
+        );
     }
 }
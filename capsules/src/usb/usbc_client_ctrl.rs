@@ -8,19 +8,26 @@ use super::descriptors::Descriptor;
 use super::descriptors::DescriptorType;
 use super::descriptors::DeviceDescriptor;
 use super::descriptors::EndpointDescriptor;
+use super::descriptors::FeatureSelector;
 use super::descriptors::HIDDescriptor;
 use super::descriptors::InterfaceDescriptor;
 use super::descriptors::LanguagesDescriptor;
 use super::descriptors::Recipient;
 use super::descriptors::ReportDescriptor;
+use super::descriptors::RequestType;
 use super::descriptors::SetupData;
+use super::descriptors::SsEndpointCompanionDescriptor;
 use super::descriptors::StandardRequest;
 use super::descriptors::StringDescriptor;
 use super::descriptors::TransferDirection;
 use core::cell::Cell;
 use core::cmp::min;
+use core::fmt;
+use kernel::common::cells::OptionalCell;
+use kernel::debug;
 use kernel::hil;
 use kernel::hil::usb::TransferType;
+use kernel::ReturnCode;
 
 const DESCRIPTOR_BUFLEN: usize = 64;
 
@@ -36,6 +43,27 @@ pub struct ClientCtrl<'a, 'b, C: 'a> {
     // A 64-byte buffer for the control endpoint
     ctrl_buffer: Buffer64,
 
+    // The most recent SETUP packet received on the control endpoint
+    last_setup: Cell<Option<SetupData>>,
+
+    // The configuration value set by the host, or zero when unconfigured
+    configuration: Cell<u8>,
+
+    // Whether the host has enabled remote wakeup with SET_FEATURE(DEVICE_REMOTE_WAKEUP)
+    remote_wakeup_enabled: Cell<bool>,
+
+    // The speed the controller was enabled at, once `enable` has been called
+    speed: Cell<Option<hil::usb::DeviceSpeed>>,
+
+    // Told about every request we stall, for debugging
+    stall_client: OptionalCell<&'a dyn StallClient>,
+
+    // Given every descriptor we serve, to patch it before it is sent
+    descriptor_filter: OptionalCell<&'a dyn DescriptorFilter>,
+
+    // Given our diagnostics instead of `debug!`
+    logger: OptionalCell<&'a dyn Logger>,
+
     // Storage for composing responses to device-descriptor requests
     descriptor_storage: [Cell<u8>; DESCRIPTOR_BUFLEN],
 
@@ -51,6 +79,9 @@ pub struct ClientCtrl<'a, 'b, C: 'a> {
     // A list of endpoints for the configuration
     endpoint_descriptors: &'b [EndpointDescriptor],
 
+    // A SuperSpeed companion for each endpoint, or none at all
+    ss_endpoint_companions: &'b [SsEndpointCompanionDescriptor],
+
     // A HID descriptor for the configuration, if any
     hid_descriptor: Option<&'b HIDDescriptor<'b>>,
 
@@ -62,53 +93,230 @@ pub struct ClientCtrl<'a, 'b, C: 'a> {
 
     // Strings
     strings: &'b [&'b str],
+
+    // A serial number served after `strings`, if any
+    serial_number: Option<&'b str>,
 }
 
-#[derive(Copy, Clone)]
-enum State {
-    Init,
+/// Notified of control requests that `ClientCtrl` could not satisfy
+///
+/// Useful during bring-up, to log exactly what the host asked for.
+pub trait StallClient {
+    /// The request in `setup_data` was answered with a STALL, for the given reason
+    fn ctrl_stalled(&self, setup_data: SetupData, result: &hil::usb::CtrlSetupResult);
+}
 
-    /// We are doing a Control In transfer of some data
-    /// in self.descriptor_storage, with the given extent
-    /// remaining to send
-    CtrlIn(usize, usize),
+/// Rewrites descriptors before `ClientCtrl` sends them to the host
+///
+/// A debugging aid for chasing host-specific enumeration problems: a board can
+/// patch individual descriptor fields without changing the capsule that
+/// declares them.
+pub trait DescriptorFilter {
+    /// `descriptor` holds the complete response to a GET_DESCRIPTOR request
+    /// for `descriptor_type`, before it is truncated to the requested length
+    fn filter_descriptor(&self, descriptor_type: DescriptorType, descriptor: &[Cell<u8>]);
+}
 
-    /// We will accept data from the host
-    CtrlOut,
+/// Receives the diagnostics of the USB capsules
+///
+/// A capsule without a logger prints them with `debug!`.  A board can send
+/// them elsewhere instead, and a test can check which error path was taken.
+pub trait Logger {
+    /// Log one line, without its line ending
+    fn log(&self, args: fmt::Arguments);
+}
 
-    SetAddress,
+/// Log `args` to `logger` if one is set, or with `debug!` otherwise
+pub fn log(logger: &OptionalCell<&dyn Logger>, args: fmt::Arguments) {
+    logger.map_or_else(|| debug!("{}", args), |logger| logger.log(args));
 }
 
-impl Default for State {
-    fn default() -> Self {
-        State::Init
-    }
+/// Assembles the descriptors served by a `ClientCtrl`
+///
+/// Every descriptor starts out with its default value, so only the ones that
+/// differ need to be given:
+///
+/// ```rust
+/// let ctrl = ClientCtrlBuilder::new(controller)
+///     .device_descriptor(DeviceDescriptor {
+///         manufacturer_string: 1,
+///         ..Default::default()
+///     })
+///     .endpoint_descriptors(ENDPOINTS)
+///     .strings(STRINGS)
+///     .build();
+/// ```
+pub struct ClientCtrlBuilder<'a, 'b, C: 'a> {
+    controller: &'a C,
+    device_descriptor: DeviceDescriptor,
+    configuration_descriptor: ConfigurationDescriptor,
+    interface_descriptor: InterfaceDescriptor,
+    endpoint_descriptors: &'b [EndpointDescriptor],
+    ss_endpoint_companions: &'b [SsEndpointCompanionDescriptor],
+    hid_descriptor: Option<&'b HIDDescriptor<'b>>,
+    report_descriptor: Option<&'b ReportDescriptor<'b>>,
+    language: &'b [u16; 1],
+    strings: &'b [&'b str],
+    serial_number: Option<&'b str>,
 }
 
-impl<'a, 'b, C: hil::usb::UsbController<'a>> ClientCtrl<'a, 'b, C> {
-    pub fn new(
-        controller: &'a C,
-        device_descriptor: DeviceDescriptor,
-        mut configuration_descriptor: ConfigurationDescriptor,
-        mut interface_descriptor: InterfaceDescriptor,
-        endpoint_descriptors: &'b [EndpointDescriptor],
-        hid_descriptor: Option<&'b HIDDescriptor<'b>>,
-        report_descriptor: Option<&'b ReportDescriptor<'b>>,
-        language: &'b [u16; 1],
-        strings: &'b [&'b str],
+impl<'a, 'b, C: hil::usb::UsbController<'a>> ClientCtrlBuilder<'a, 'b, C> {
+    pub fn new(controller: &'a C) -> Self {
+        ClientCtrlBuilder {
+            controller,
+            device_descriptor: Default::default(),
+            configuration_descriptor: Default::default(),
+            interface_descriptor: Default::default(),
+            endpoint_descriptors: &[],
+            ss_endpoint_companions: &[],
+            hid_descriptor: None,
+            report_descriptor: None,
+            language: &[
+                0x0409, // English (United States)
+            ],
+            strings: &[],
+            serial_number: None,
+        }
+    }
+
+    pub fn device_descriptor(mut self, device_descriptor: DeviceDescriptor) -> Self {
+        self.device_descriptor = device_descriptor;
+        self
+    }
+
+    pub fn configuration_descriptor(
+        mut self,
+        configuration_descriptor: ConfigurationDescriptor,
+    ) -> Self {
+        self.configuration_descriptor = configuration_descriptor;
+        self
+    }
+
+    pub fn interface_descriptor(mut self, interface_descriptor: InterfaceDescriptor) -> Self {
+        self.interface_descriptor = interface_descriptor;
+        self
+    }
+
+    pub fn endpoint_descriptors(mut self, endpoint_descriptors: &'b [EndpointDescriptor]) -> Self {
+        self.endpoint_descriptors = endpoint_descriptors;
+        self
+    }
+
+    /// Give each endpoint a SuperSpeed Endpoint Companion descriptor, in the same order as
+    /// `endpoint_descriptors`
+    pub fn ss_endpoint_companions(
+        mut self,
+        ss_endpoint_companions: &'b [SsEndpointCompanionDescriptor],
+    ) -> Self {
+        self.ss_endpoint_companions = ss_endpoint_companions;
+        self
+    }
+
+    /// Declare the interface as a HID interface with the given report descriptor
+    pub fn hid(
+        mut self,
+        hid_descriptor: &'b HIDDescriptor<'b>,
+        report_descriptor: &'b ReportDescriptor<'b>,
     ) -> Self {
+        self.hid_descriptor = Some(hid_descriptor);
+        self.report_descriptor = Some(report_descriptor);
+        self
+    }
+
+    pub fn language(mut self, language: &'b [u16; 1]) -> Self {
+        self.language = language;
+        self
+    }
+
+    pub fn strings(mut self, strings: &'b [&'b str]) -> Self {
+        self.strings = strings;
+        self
+    }
+
+    /// Serve `serial_number` as the serial number string, at the index after `strings`
+    ///
+    /// This sets the serial number index of the device descriptor, so that the
+    /// serial number can be chosen at run time while the other strings stay static.
+    /// An empty `serial_number` means the device has none.
+    pub fn serial_number(mut self, serial_number: &'b str) -> Self {
+        self.serial_number = if serial_number.is_empty() {
+            None
+        } else {
+            Some(serial_number)
+        };
+        self
+    }
+
+    /// Check the descriptors for consistency and construct the `ClientCtrl`
+    pub fn build(self) -> ClientCtrl<'a, 'b, C> {
+        // At full speed the default control endpoint must use one of these packet sizes.
+        match self.device_descriptor.max_packet_size_ep0 {
+            8 | 16 | 32 | 64 => {}
+            size => panic!("Invalid max packet size for endpoint 0: {}", size),
+        }
+
+        // A HID interface is useless without its report descriptor, and vice versa.
+        if self.hid_descriptor.is_some() != self.report_descriptor.is_some() {
+            panic!("HID and report descriptors must be provided together");
+        }
+
+        let mut device_descriptor = self.device_descriptor;
+        let mut num_strings = self.strings.len();
+        if self.serial_number.is_some() {
+            num_strings += 1;
+            device_descriptor.serial_number_string = num_strings as u8;
+        }
+
+        // Index zero means the device has no such string; any other index must be in the table.
+        for &index in &[
+            device_descriptor.manufacturer_string,
+            device_descriptor.product_string,
+            device_descriptor.serial_number_string,
+            self.configuration_descriptor.string_index,
+            self.interface_descriptor.string_index,
+        ] {
+            if index as usize > num_strings {
+                panic!("String index {} is beyond the string table", index);
+            }
+        }
+
+        // Companion descriptors come one per endpoint, or not at all.
+        if !self.ss_endpoint_companions.is_empty()
+            && self.ss_endpoint_companions.len() != self.endpoint_descriptors.len()
+        {
+            panic!("Each endpoint needs its own SuperSpeed companion descriptor");
+        }
+
         // Tweak the configuration/interface descriptors for the given endpoints.
-        interface_descriptor.num_endpoints = endpoint_descriptors.len() as u8;
+        let mut interface_descriptor = self.interface_descriptor;
+        interface_descriptor.num_endpoints = self.endpoint_descriptors.len() as u8;
 
+        let mut configuration_descriptor = self.configuration_descriptor;
         configuration_descriptor.num_interfaces = 1;
         configuration_descriptor.related_descriptor_length = interface_descriptor.size()
-            + endpoint_descriptors.iter().map(|d| d.size()).sum::<usize>()
-            + hid_descriptor.map_or(0, |d| d.size());
+            + self
+                .endpoint_descriptors
+                .iter()
+                .map(|d| d.size())
+                .sum::<usize>()
+            + self
+                .ss_endpoint_companions
+                .iter()
+                .map(|d| d.size())
+                .sum::<usize>()
+            + self.hid_descriptor.map_or(0, |d| d.size());
 
         ClientCtrl {
-            controller: controller,
+            controller: self.controller,
             state: Default::default(),
             ctrl_buffer: Default::default(),
+            last_setup: Cell::new(None),
+            configuration: Cell::new(0),
+            remote_wakeup_enabled: Cell::new(false),
+            speed: Cell::new(None),
+            stall_client: OptionalCell::empty(),
+            descriptor_filter: OptionalCell::empty(),
+            logger: OptionalCell::empty(),
             // For the moment, the Default trait is not implemented for arrays of length > 32, and
             // the Cell type is not Copy, so we have to initialize each element manually.
             descriptor_storage: [
@@ -180,19 +388,168 @@ impl<'a, 'b, C: hil::usb::UsbController<'a>> ClientCtrl<'a, 'b, C> {
             device_descriptor,
             configuration_descriptor,
             interface_descriptor,
-            endpoint_descriptors,
-            hid_descriptor,
-            report_descriptor,
-            language,
-            strings,
+            endpoint_descriptors: self.endpoint_descriptors,
+            ss_endpoint_companions: self.ss_endpoint_companions,
+            hid_descriptor: self.hid_descriptor,
+            report_descriptor: self.report_descriptor,
+            language: self.language,
+            strings: self.strings,
+            serial_number: self.serial_number,
         }
     }
+}
+
+#[derive(Copy, Clone, Debug)]
+enum State {
+    Init,
+
+    /// We are doing a Control In transfer of some data
+    /// in self.descriptor_storage, with the given extent
+    /// remaining to send
+    CtrlIn(usize, usize),
+
+    /// We will accept data from the host
+    CtrlOut,
+
+    SetAddress,
+
+    /// We will switch to the given configuration once the Status stage completes
+    SetConfiguration(u8),
+}
+
+impl Default for State {
+    fn default() -> Self {
+        State::Init
+    }
+}
+
+impl<'a, 'b, C: hil::usb::UsbController<'a>> ClientCtrl<'a, 'b, C> {
+    pub fn new(
+        controller: &'a C,
+        device_descriptor: DeviceDescriptor,
+        configuration_descriptor: ConfigurationDescriptor,
+        interface_descriptor: InterfaceDescriptor,
+        endpoint_descriptors: &'b [EndpointDescriptor],
+        hid_descriptor: Option<&'b HIDDescriptor<'b>>,
+        report_descriptor: Option<&'b ReportDescriptor<'b>>,
+        language: &'b [u16; 1],
+        strings: &'b [&'b str],
+    ) -> Self {
+        let mut builder = ClientCtrlBuilder::new(controller)
+            .device_descriptor(device_descriptor)
+            .configuration_descriptor(configuration_descriptor)
+            .interface_descriptor(interface_descriptor)
+            .endpoint_descriptors(endpoint_descriptors)
+            .language(language)
+            .strings(strings);
+        builder.hid_descriptor = hid_descriptor;
+        builder.report_descriptor = report_descriptor;
+        builder.build()
+    }
 
     #[inline]
     pub fn controller(&'a self) -> &'a C {
         self.controller
     }
 
+    /// The number of the interface we serve, which class requests carry in `wIndex`
+    #[inline]
+    pub fn interface_number(&self) -> u8 {
+        self.interface_descriptor.interface_number
+    }
+
+    /// The most recent SETUP packet received on the control endpoint, if any
+    ///
+    /// This lets clients that handle class or vendor requests see the raw
+    /// request fields.
+    pub fn last_setup(&self) -> Option<SetupData> {
+        self.last_setup.get()
+    }
+
+    /// The configuration value last set by the host, or zero when unconfigured
+    pub fn configuration(&self) -> u8 {
+        self.configuration.get()
+    }
+
+    /// Register a client to be told about stalled control requests
+    pub fn set_stall_client(&self, client: &'a dyn StallClient) {
+        self.stall_client.set(client);
+    }
+
+    /// Register a filter to rewrite descriptors before they are sent
+    pub fn set_descriptor_filter(&self, filter: &'a dyn DescriptorFilter) {
+        self.descriptor_filter.set(filter);
+    }
+
+    /// Register a logger to receive our diagnostics instead of `debug!`
+    pub fn set_logger(&self, logger: &'a dyn Logger) {
+        self.logger.set(logger);
+    }
+
+    /// Log `args` to our logger, or with `debug!` if there is none
+    pub fn log(&self, args: fmt::Arguments) {
+        log(&self.logger, args);
+    }
+
+    /// Whether the host allows us to signal remote wakeup
+    pub fn remote_wakeup_enabled(&self) -> bool {
+        self.remote_wakeup_enabled.get()
+    }
+
+    /// The speed the device operates at, or `None` before `enable`
+    pub fn speed(&self) -> Option<hil::usb::DeviceSpeed> {
+        self.speed.get()
+    }
+
+    /// Parse the SETUP packet just received on the control endpoint
+    ///
+    /// Clients that answer class requests should do so from the hook they
+    /// give `ctrl_setup_with`, which sees the parsed request.
+    pub fn setup_data(&self) -> Option<SetupData> {
+        let setup_data = SetupData::get(&self.ctrl_buffer.buf);
+        self.last_setup.set(setup_data);
+        setup_data
+    }
+
+    /// Answer the current control IN request with `data`, or as much of it as
+    /// the host requested
+    pub fn ctrl_in_reply(
+        &'a self,
+        endpoint: usize,
+        data: &[u8],
+        requested_length: u16,
+    ) -> hil::usb::CtrlSetupResult {
+        let buf = self.descriptor_buf();
+        if data.len() > buf.len() {
+            return hil::usb::CtrlSetupResult::ErrGeneric;
+        }
+        for (i, b) in data.iter().enumerate() {
+            buf[i].set(*b);
+        }
+        let end = min(data.len(), requested_length as usize);
+        self.state[endpoint].set(State::CtrlIn(0, end));
+        hil::usb::CtrlSetupResult::Ok
+    }
+
+    /// Print the control state, for debugging: a line for the device, then one per endpoint
+    pub fn dump_state(&'a self) {
+        self.log(format_args!(
+            "usb_ctrl: interface={} configuration={} speed={:?} remote_wakeup={} last_setup={:?}",
+            self.interface_number(),
+            self.configuration.get(),
+            self.speed.get(),
+            self.remote_wakeup_enabled.get(),
+            self.last_setup.get(),
+        ));
+        for (endpoint, state) in self.state.iter().enumerate() {
+            self.log(format_args!(
+                "usb_ctrl: endpoint {} state={:?}",
+                endpoint,
+                state.get()
+            ));
+        }
+    }
+
     #[inline]
     fn descriptor_buf(&'a self) -> &'a [Cell<u8>] {
         &self.descriptor_storage
@@ -202,60 +559,141 @@ impl<'a, 'b, C: hil::usb::UsbController<'a>> ClientCtrl<'a, 'b, C> {
         // Set up the default control endpoint
         self.controller
             .endpoint_set_buffer(0, &self.ctrl_buffer.buf);
-        self.controller
-            .enable_as_device(hil::usb::DeviceSpeed::Full); // must be Full for Bulk transfers
+        let speed = hil::usb::DeviceSpeed::Full; // must be Full for Bulk transfers
+        self.controller.enable_as_device(speed);
+        self.speed.set(Some(speed));
         self.controller
             .endpoint_out_enable(TransferType::Control, 0);
     }
 
-    pub fn attach(&'a self) {
+    /// Attach to the bus, which must only happen once `enable` has set up the controller
+    ///
+    /// Returns `EOFF`, without touching the controller, if called before `enable`.
+    pub fn attach(&'a self) -> ReturnCode {
+        if self.speed.get().is_none() {
+            return ReturnCode::EOFF;
+        }
         self.controller.attach();
+        ReturnCode::SUCCESS
+    }
+
+    /// Forget the configuration and features set by the host, as a bus reset returns the
+    /// device to the Default state
+    pub fn bus_reset(&'a self) {
+        self.configuration.set(0);
+        self.remote_wakeup_enabled.set(false);
+        for state in self.state.iter() {
+            state.set(State::Init);
+        }
     }
 
     /// Handle a Control Setup transaction
     pub fn ctrl_setup(&'a self, endpoint: usize) -> hil::usb::CtrlSetupResult {
+        self.ctrl_setup_with(endpoint, |_| None)
+    }
+
+    /// Handle a Control Setup transaction, letting `class_request` answer class requests
+    ///
+    /// `class_request` sees every class request once any transfer in progress has been
+    /// aborted, and returns `None` for those it does not handle.  These are stalled, and
+    /// like any other stall reported to the stall client.
+    pub fn ctrl_setup_with<F>(
+        &'a self,
+        endpoint: usize,
+        class_request: F,
+    ) -> hil::usb::CtrlSetupResult
+    where
+        F: FnOnce(SetupData) -> Option<hil::usb::CtrlSetupResult>,
+    {
         if endpoint != 0 {
             // For now we only support the default Control endpoint
             return hil::usb::CtrlSetupResult::ErrInvalidDeviceIndex;
         }
-        SetupData::get(&self.ctrl_buffer.buf).map_or(
-            hil::usb::CtrlSetupResult::ErrNoParse,
-            |setup_data| {
-                let transfer_direction = setup_data.request_type.transfer_direction();
-                let recipient = setup_data.request_type.recipient();
-                setup_data.get_standard_request().map_or_else(
-                    || {
-                        // XX: CtrlSetupResult::ErrNonstandardRequest
-
-                        // For now, promiscuously accept vendor data and even supply
-                        // a few debugging bytes when host does a read
-
-                        match transfer_direction {
-                            TransferDirection::HostToDevice => {
-                                self.state[endpoint].set(State::CtrlOut);
-                                hil::usb::CtrlSetupResult::Ok
+
+        // A SETUP packet aborts any control transfer still in progress, so that data from
+        // it cannot leak into the reply to this request
+        self.state[endpoint].set(State::Init);
+
+        let result =
+            self.setup_data()
+                .map_or(hil::usb::CtrlSetupResult::ErrNoParse, |setup_data| {
+                    if let RequestType::Class = setup_data.request_type.request_type() {
+                        return class_request(setup_data)
+                            .unwrap_or(hil::usb::CtrlSetupResult::ErrUnrecognizedRequestType);
+                    }
+
+                    let transfer_direction = setup_data.request_type.transfer_direction();
+                    let recipient = setup_data.request_type.recipient();
+                    setup_data.get_standard_request().map_or_else(
+                        || {
+                            if let RequestType::Standard = setup_data.request_type.request_type() {
+                                // A standard request we could not parse (e.g. SET_DESCRIPTOR for a
+                                // descriptor type that cannot be set) must not be mistaken for
+                                // vendor data
+                                return hil::usb::CtrlSetupResult::ErrUnrecognizedRequestType;
                             }
-                            TransferDirection::DeviceToHost => {
-                                // Arrange to send some crap back
-                                let buf = self.descriptor_buf();
-                                buf[0].set(0xa);
-                                buf[1].set(0xb);
-                                buf[2].set(0xc);
-                                self.state[endpoint].set(State::CtrlIn(0, 3));
-                                hil::usb::CtrlSetupResult::Ok
+
+                            // XX: CtrlSetupResult::ErrNonstandardRequest
+
+                            // For now, promiscuously accept vendor data and even supply
+                            // a few debugging bytes when host does a read
+
+                            match transfer_direction {
+                                TransferDirection::HostToDevice => {
+                                    self.state[endpoint].set(State::CtrlOut);
+                                    hil::usb::CtrlSetupResult::Ok
+                                }
+                                TransferDirection::DeviceToHost => {
+                                    // Arrange to send some crap back
+                                    let buf = self.descriptor_buf();
+                                    buf[0].set(0xa);
+                                    buf[1].set(0xb);
+                                    buf[2].set(0xc);
+                                    self.state[endpoint].set(State::CtrlIn(0, 3));
+                                    hil::usb::CtrlSetupResult::Ok
+                                }
                             }
-                        }
-                    },
-                    |request| match recipient {
-                        Recipient::Device => self.handle_standard_device_request(endpoint, request),
-                        Recipient::Interface => {
-                            self.handle_standard_interface_request(endpoint, request)
-                        }
-                        _ => hil::usb::CtrlSetupResult::ErrGeneric,
-                    },
-                )
-            },
-        )
+                        },
+                        |request| match recipient {
+                            Recipient::Device => {
+                                self.handle_standard_device_request(endpoint, request)
+                            }
+                            Recipient::Interface => {
+                                self.handle_standard_interface_request(endpoint, request)
+                            }
+                            _ => hil::usb::CtrlSetupResult::ErrGeneric,
+                        },
+                    )
+                });
+
+        match result {
+            hil::usb::CtrlSetupResult::Ok | hil::usb::CtrlSetupResult::OkSetAddress => {}
+            _ => {
+                // Any other result makes the controller stall the request
+                if let Some(setup_data) = self.last_setup.get() {
+                    self.stall_client
+                        .map(|client| client.ctrl_stalled(setup_data, &result));
+                }
+            }
+        }
+        result
+    }
+
+    /// Send the first `len` bytes of `descriptor_buf()` in reply to a GET_DESCRIPTOR request
+    fn descriptor_reply(
+        &'a self,
+        endpoint: usize,
+        descriptor_type: DescriptorType,
+        len: usize,
+        requested_length: u16,
+    ) -> hil::usb::CtrlSetupResult {
+        let buf = self.descriptor_buf();
+        self.descriptor_filter
+            .map(|filter| filter.filter_descriptor(descriptor_type, &buf[..len]));
+
+        let end = min(len, requested_length as usize);
+        self.state[endpoint].set(State::CtrlIn(0, end));
+        hil::usb::CtrlSetupResult::Ok
     }
 
     fn handle_standard_device_request(
@@ -276,9 +714,12 @@ impl<'a, 'b, C: hil::usb::UsbController<'a>> ClientCtrl<'a, 'b, C> {
                             let buf = self.descriptor_buf();
                             let len = self.device_descriptor.write_to(buf);
 
-                            let end = min(len, requested_length as usize);
-                            self.state[endpoint].set(State::CtrlIn(0, end));
-                            hil::usb::CtrlSetupResult::Ok
+                            self.descriptor_reply(
+                                endpoint,
+                                DescriptorType::Device,
+                                len,
+                                requested_length,
+                            )
                         }
                         _ => hil::usb::CtrlSetupResult::ErrInvalidDeviceIndex,
                     },
@@ -302,14 +743,20 @@ impl<'a, 'b, C: hil::usb::UsbController<'a>> ClientCtrl<'a, 'b, C> {
                                     len += dh.write_to(&buf[len..]);
                                 }
 
-                                // Endpoints.
-                                for de in self.endpoint_descriptors {
+                                // Endpoints, each followed by its companion, if any.
+                                for (i, de) in self.endpoint_descriptors.iter().enumerate() {
                                     len += de.write_to(&buf[len..]);
+                                    if let Some(dc) = self.ss_endpoint_companions.get(i) {
+                                        len += dc.write_to(&buf[len..]);
+                                    }
                                 }
 
-                                let end = min(len, requested_length as usize);
-                                self.state[endpoint].set(State::CtrlIn(0, end));
-                                hil::usb::CtrlSetupResult::Ok
+                                self.descriptor_reply(
+                                    endpoint,
+                                    DescriptorType::Configuration,
+                                    len,
+                                    requested_length,
+                                )
                             }
                             _ => hil::usb::CtrlSetupResult::ErrInvalidConfigurationIndex,
                         }
@@ -319,9 +766,12 @@ impl<'a, 'b, C: hil::usb::UsbController<'a>> ClientCtrl<'a, 'b, C> {
                             let buf = self.descriptor_buf();
                             let len = self.interface_descriptor.write_to(buf);
 
-                            let end = min(len, requested_length as usize);
-                            self.state[endpoint].set(State::CtrlIn(0, end));
-                            hil::usb::CtrlSetupResult::Ok
+                            self.descriptor_reply(
+                                endpoint,
+                                DescriptorType::Interface,
+                                len,
+                                requested_length,
+                            )
                         }
                         _ => hil::usb::CtrlSetupResult::ErrInvalidInterfaceIndex,
                     },
@@ -346,11 +796,25 @@ impl<'a, 'b, C: hil::usb::UsbController<'a>> ClientCtrl<'a, 'b, C> {
                                 let len = d.write_to(buf);
                                 Some(len)
                             }
+                            i if i as usize == self.strings.len() + 1
+                                && lang_id == self.language[0] =>
+                            {
+                                self.serial_number.map(|serial_number| {
+                                    let buf = self.descriptor_buf();
+                                    let d = StringDescriptor {
+                                        string: serial_number,
+                                    };
+                                    d.write_to(buf)
+                                })
+                            }
                             _ => None,
                         } {
-                            let end = min(len, requested_length as usize);
-                            self.state[endpoint].set(State::CtrlIn(0, end));
-                            hil::usb::CtrlSetupResult::Ok
+                            self.descriptor_reply(
+                                endpoint,
+                                DescriptorType::String,
+                                len,
+                                requested_length,
+                            )
                         } else {
                             hil::usb::CtrlSetupResult::ErrInvalidStringIndex
                         }
@@ -363,6 +827,35 @@ impl<'a, 'b, C: hil::usb::UsbController<'a>> ClientCtrl<'a, 'b, C> {
                     _ => hil::usb::CtrlSetupResult::ErrUnrecognizedDescriptorType,
                 } // match descriptor_type
             }
+            StandardRequest::GetStatus { .. } => {
+                // Bit 0 is Self Powered; bit 1 is Remote Wakeup
+                let self_powered = self.configuration_descriptor.attributes.is_self_powered();
+                let status = self_powered as u8 | (self.remote_wakeup_enabled.get() as u8) << 1;
+                self.ctrl_in_reply(endpoint, &[status, 0], 2)
+            }
+            StandardRequest::SetFeature {
+                feature: FeatureSelector::DeviceRemoteWakeup,
+                ..
+            }
+            | StandardRequest::ClearFeature {
+                feature: FeatureSelector::DeviceRemoteWakeup,
+                ..
+            } => {
+                // Only a device that declares remote wakeup support may have it enabled
+                if !self
+                    .configuration_descriptor
+                    .attributes
+                    .supports_remote_wakeup()
+                {
+                    return hil::usb::CtrlSetupResult::ErrGeneric;
+                }
+                let enable = match request {
+                    StandardRequest::SetFeature { .. } => true,
+                    _ => false,
+                };
+                self.remote_wakeup_enabled.set(enable);
+                hil::usb::CtrlSetupResult::Ok
+            }
             StandardRequest::SetAddress { device_address } => {
                 // Load the address we've been assigned ...
                 self.controller.set_address(device_address);
@@ -372,10 +865,22 @@ impl<'a, 'b, C: hil::usb::UsbController<'a>> ClientCtrl<'a, 'b, C> {
                 self.state[endpoint].set(State::SetAddress);
                 hil::usb::CtrlSetupResult::OkSetAddress
             }
-            StandardRequest::SetConfiguration { .. } => {
-                // We have been assigned a particular configuration: fine!
+            StandardRequest::GetConfiguration => {
+                self.ctrl_in_reply(endpoint, &[self.configuration.get()], 1)
+            }
+            StandardRequest::SetConfiguration {
+                configuration_value,
+            } => {
+                // We have been assigned a particular configuration: fine!  It takes effect
+                // only once the Status stage has completed.
+                self.state[endpoint].set(State::SetConfiguration(configuration_value));
                 hil::usb::CtrlSetupResult::Ok
             }
+            StandardRequest::SetDescriptor { .. } => {
+                // Our descriptors are fixed when the client is built, so the host may not
+                // replace them
+                hil::usb::CtrlSetupResult::ErrUnrecognizedRequestType
+            }
             _ => hil::usb::CtrlSetupResult::ErrUnrecognizedRequestType,
         }
     }
@@ -388,19 +893,23 @@ impl<'a, 'b, C: hil::usb::UsbController<'a>> ClientCtrl<'a, 'b, C> {
         match request {
             StandardRequest::GetDescriptor {
                 descriptor_type,
-                // TODO: use the descriptor index
-                descriptor_index: _,
-                // TODO: use the language ID?
-                lang_id: _,
+                descriptor_index,
+                // For requests addressed to an interface, wIndex holds the interface number
+                lang_id: interface,
                 requested_length,
             } => match descriptor_type {
+                _ if interface != self.interface_number() as u16 => {
+                    hil::usb::CtrlSetupResult::ErrInvalidInterfaceIndex
+                }
+                // We have a single HID descriptor, which describes a single report descriptor
+                DescriptorType::HID | DescriptorType::Report if descriptor_index != 0 => {
+                    hil::usb::CtrlSetupResult::ErrUnrecognizedDescriptorType
+                }
                 DescriptorType::HID => {
                     if let Some(desc) = self.hid_descriptor {
                         let buf = self.descriptor_buf();
                         let len = desc.write_to(buf);
-                        let end = min(len, requested_length as usize);
-                        self.state[endpoint].set(State::CtrlIn(0, end));
-                        hil::usb::CtrlSetupResult::Ok
+                        self.descriptor_reply(endpoint, DescriptorType::HID, len, requested_length)
                     } else {
                         hil::usb::CtrlSetupResult::ErrGeneric
                     }
@@ -409,15 +918,26 @@ impl<'a, 'b, C: hil::usb::UsbController<'a>> ClientCtrl<'a, 'b, C> {
                     if let Some(desc) = self.report_descriptor {
                         let buf = self.descriptor_buf();
                         let len = desc.write_to(buf);
-                        let end = min(len, requested_length as usize);
-                        self.state[endpoint].set(State::CtrlIn(0, end));
-                        hil::usb::CtrlSetupResult::Ok
+                        self.descriptor_reply(
+                            endpoint,
+                            DescriptorType::Report,
+                            len,
+                            requested_length,
+                        )
                     } else {
                         hil::usb::CtrlSetupResult::ErrGeneric
                     }
                 }
-                _ => hil::usb::CtrlSetupResult::ErrGeneric,
+                _ => hil::usb::CtrlSetupResult::ErrUnrecognizedDescriptorType,
             },
+            StandardRequest::GetStatus { recipient_index } => {
+                if recipient_index == self.interface_number() as u16 {
+                    // All bits of the interface status are reserved
+                    self.ctrl_in_reply(endpoint, &[0, 0], 2)
+                } else {
+                    hil::usb::CtrlSetupResult::ErrInvalidInterfaceIndex
+                }
+            }
             _ => hil::usb::CtrlSetupResult::ErrGeneric,
         }
     }
@@ -428,7 +948,11 @@ impl<'a, 'b, C: hil::usb::UsbController<'a>> ClientCtrl<'a, 'b, C> {
             State::CtrlIn(start, end) => {
                 let len = end.saturating_sub(start);
                 if len > 0 {
-                    let packet_bytes = min(self.ctrl_buffer.buf.len(), len);
+                    let max_packet_size = min(
+                        self.ctrl_buffer.buf.len(),
+                        self.device_descriptor.max_packet_size_ep0 as usize,
+                    );
+                    let packet_bytes = min(max_packet_size, len);
                     let packet = &self.descriptor_storage[start..start + packet_bytes];
                     let buf = &self.ctrl_buffer.buf;
 
@@ -479,8 +1003,973 @@ impl<'a, 'b, C: hil::usb::UsbController<'a>> ClientCtrl<'a, 'b, C> {
             State::SetAddress => {
                 self.controller.enable_address();
             }
+            State::SetConfiguration(configuration_value) => {
+                self.configuration.set(configuration_value);
+            }
             _ => {}
         };
         self.state[endpoint].set(State::Init);
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::super::descriptors::{
+        ConfigurationAttributes, ConfigurationDescriptor, DescriptorType, DeviceDescriptor,
+        EndpointAddress, EndpointDescriptor, HIDCountryCode, HIDDescriptor,
+        HIDSubordinateDescriptor, InterfaceDescriptor, ReportDescriptor, RequestType, SetupData,
+        SsEndpointCompanionDescriptor, TransferDirection,
+    };
+    use super::super::mock::{MockController, MockLogger};
+    use super::{ClientCtrl, ClientCtrlBuilder, DescriptorFilter, StallClient};
+    use core::cell::Cell;
+    use kernel::hil::usb::{CtrlInResult, CtrlSetupResult, DeviceSpeed, TransferType};
+    use kernel::ReturnCode;
+
+    static LANGUAGES: &[u16; 1] = &[0x0409];
+    static STRINGS: &[&str] = &["A string needing two packets"];
+    static NO_ENDPOINTS: &[EndpointDescriptor] = &[];
+
+    static HID_ENDPOINTS: &[EndpointDescriptor] = &[
+        EndpointDescriptor {
+            endpoint_address: EndpointAddress::new_const(1, TransferDirection::DeviceToHost),
+            transfer_type: TransferType::Interrupt,
+            max_packet_size: 64,
+            interval: 5,
+        },
+        EndpointDescriptor {
+            endpoint_address: EndpointAddress::new_const(1, TransferDirection::HostToDevice),
+            transfer_type: TransferType::Interrupt,
+            max_packet_size: 64,
+            interval: 5,
+        },
+    ];
+
+    static REPORT: ReportDescriptor<'static> = ReportDescriptor {
+        desc: &[
+            0x06, 0xD0, 0xF1, // Usage Page (FIDO)
+            0x09, 0x01, // Usage (U2F Authenticator Device)
+            0xA1, 0x01, // Collection (Application)
+            0xC0, // End Collection
+        ],
+    };
+
+    static SUB_HID_DESCRIPTORS: &[HIDSubordinateDescriptor] = &[HIDSubordinateDescriptor {
+        typ: DescriptorType::Report,
+        len: 8,
+    }];
+
+    static HID: HIDDescriptor<'static> = HIDDescriptor {
+        hid_class: 0x0110,
+        country_code: HIDCountryCode::NotSupported,
+        sub_descriptors: SUB_HID_DESCRIPTORS,
+    };
+
+    fn new_ctrl(
+        controller: &'a MockController<'a>,
+        max_packet_size_ep0: u8,
+    ) -> ClientCtrl<'a, 'static, MockController<'a>> {
+        ClientCtrl::new(
+            controller,
+            DeviceDescriptor {
+                max_packet_size_ep0,
+                ..Default::default()
+            },
+            Default::default(),
+            Default::default(),
+            NO_ENDPOINTS,
+            None,
+            None,
+            LANGUAGES,
+            STRINGS,
+        )
+    }
+
+    /// Place a SETUP packet in the control endpoint buffer, as the controller would
+    fn setup(ctrl: &ClientCtrl<'a, 'static, MockController<'a>>, packet: [u8; 8]) {
+        for (i, b) in packet.iter().enumerate() {
+            ctrl.ctrl_buffer.buf[i].set(*b);
+        }
+    }
+
+    /// Run a whole control read, returning the number of bytes sent
+    fn control_read(
+        ctrl: &'a ClientCtrl<'a, 'static, MockController<'a>>,
+        packet: [u8; 8],
+    ) -> usize {
+        setup(ctrl, packet);
+        assert!(match ctrl.ctrl_setup(0) {
+            CtrlSetupResult::Ok => true,
+            _ => false,
+        });
+        let mut total = 0;
+        loop {
+            match ctrl.ctrl_in(0) {
+                CtrlInResult::Packet(n, complete) => {
+                    total += n;
+                    if complete {
+                        break;
+                    }
+                }
+                _ => panic!("control read failed"),
+            }
+        }
+        ctrl.ctrl_status(0);
+        ctrl.ctrl_status_complete(0);
+        total
+    }
+
+    #[test]
+    pub fn ctrl_in_respects_max_packet_size_ep0() {
+        let controller = MockController::new();
+        let ctrl = new_ctrl(&controller, 32);
+
+        // GET_DESCRIPTOR(String 1, en-US) for up to 255 bytes
+        setup(&ctrl, [0x80, 0x06, 0x01, 0x03, 0x09, 0x04, 0xff, 0x00]);
+        assert!(match ctrl.ctrl_setup(0) {
+            CtrlSetupResult::Ok => true,
+            _ => false,
+        });
+
+        let string_len = 2 + 2 * STRINGS[0].len();
+        assert!(match ctrl.ctrl_in(0) {
+            CtrlInResult::Packet(32, false) => true,
+            _ => false,
+        });
+        assert!(match ctrl.ctrl_in(0) {
+            CtrlInResult::Packet(n, true) => n == string_len - 32,
+            _ => false,
+        });
+    }
+
+    #[test]
+    #[should_panic]
+    pub fn invalid_max_packet_size_ep0() {
+        let controller = MockController::new();
+        new_ctrl(&controller, 48);
+    }
+
+    #[test]
+    pub fn builder_assembles_hid_configuration() {
+        let controller = MockController::new();
+        let ctrl = ClientCtrlBuilder::new(&controller)
+            .device_descriptor(DeviceDescriptor {
+                max_packet_size_ep0: 64,
+                ..Default::default()
+            })
+            .endpoint_descriptors(HID_ENDPOINTS)
+            .hid(&HID, &REPORT)
+            .build();
+
+        assert_eq!(ctrl.interface_descriptor.num_endpoints, 2);
+
+        // GET_DESCRIPTOR(Configuration 0) for up to 255 bytes
+        setup(&ctrl, [0x80, 0x06, 0x00, 0x02, 0x00, 0x00, 0xff, 0x00]);
+        assert!(match ctrl.ctrl_setup(0) {
+            CtrlSetupResult::Ok => true,
+            _ => false,
+        });
+
+        // Configuration, interface, HID and two endpoint descriptors
+        let total_len = 9 + 9 + 9 + 7 + 7;
+        assert!(match ctrl.ctrl_in(0) {
+            CtrlInResult::Packet(n, true) => n == total_len,
+            _ => false,
+        });
+        assert_eq!(ctrl.ctrl_buffer.buf[2].get() as usize, total_len);
+        assert_eq!(ctrl.ctrl_buffer.buf[19].get(), DescriptorType::HID as u8);
+    }
+
+    #[test]
+    #[should_panic]
+    pub fn builder_rejects_hid_without_report() {
+        let controller = MockController::new();
+        let mut builder = ClientCtrlBuilder::new(&controller);
+        builder.hid_descriptor = Some(&HID);
+        builder.build();
+    }
+
+    #[test]
+    pub fn set_descriptor_stalls() {
+        let controller = MockController::new();
+        let ctrl = new_ctrl(&controller, 8);
+
+        // SET_DESCRIPTOR(Device), which parses as a standard request
+        setup(&ctrl, [0x00, 0x07, 0x00, 0x01, 0x00, 0x00, 0x12, 0x00]);
+        assert!(match ctrl.ctrl_setup(0) {
+            CtrlSetupResult::ErrUnrecognizedRequestType => true,
+            _ => false,
+        });
+
+        // SET_DESCRIPTOR(Interface), which is not a settable descriptor type
+        setup(&ctrl, [0x00, 0x07, 0x00, 0x04, 0x00, 0x00, 0x09, 0x00]);
+        assert!(match ctrl.ctrl_setup(0) {
+            CtrlSetupResult::ErrUnrecognizedRequestType => true,
+            _ => false,
+        });
+    }
+
+    #[test]
+    pub fn get_hid_and_report_descriptors() {
+        let controller = MockController::new();
+        let ctrl = ClientCtrlBuilder::new(&controller)
+            .device_descriptor(DeviceDescriptor {
+                max_packet_size_ep0: 64,
+                ..Default::default()
+            })
+            .endpoint_descriptors(HID_ENDPOINTS)
+            .hid(&HID, &REPORT)
+            .build();
+
+        // GET_DESCRIPTOR(HID) on interface 0
+        setup(&ctrl, [0x81, 0x06, 0x00, 0x21, 0x00, 0x00, 0xff, 0x00]);
+        assert!(match ctrl.ctrl_setup(0) {
+            CtrlSetupResult::Ok => true,
+            _ => false,
+        });
+        assert!(match ctrl.ctrl_in(0) {
+            CtrlInResult::Packet(9, true) => true,
+            _ => false,
+        });
+        assert_eq!(ctrl.ctrl_buffer.buf[1].get(), DescriptorType::HID as u8);
+
+        // GET_DESCRIPTOR(Report) on interface 0
+        setup(&ctrl, [0x81, 0x06, 0x00, 0x22, 0x00, 0x00, 0xff, 0x00]);
+        assert!(match ctrl.ctrl_setup(0) {
+            CtrlSetupResult::Ok => true,
+            _ => false,
+        });
+        assert!(match ctrl.ctrl_in(0) {
+            CtrlInResult::Packet(8, true) => true,
+            _ => false,
+        });
+        assert_eq!(ctrl.ctrl_buffer.buf[0].get(), REPORT.desc[0]);
+
+        // GET_DESCRIPTOR(Report) on an interface we do not have
+        setup(&ctrl, [0x81, 0x06, 0x00, 0x22, 0x01, 0x00, 0xff, 0x00]);
+        assert!(match ctrl.ctrl_setup(0) {
+            CtrlSetupResult::ErrInvalidInterfaceIndex => true,
+            _ => false,
+        });
+
+        // GET_DESCRIPTOR(HID, index 1) and GET_DESCRIPTOR(Report, index 1) on interface 0, which
+        // has only the first of each
+        for &descriptor_type in &[0x21, 0x22] {
+            setup(
+                &ctrl,
+                [0x81, 0x06, 0x01, descriptor_type, 0x00, 0x00, 0xff, 0x00],
+            );
+            assert!(match ctrl.ctrl_setup(0) {
+                CtrlSetupResult::ErrUnrecognizedDescriptorType => true,
+                _ => false,
+            });
+        }
+    }
+
+    #[test]
+    pub fn interface_number_matches_descriptor() {
+        let controller = MockController::new();
+        let ctrl = ClientCtrlBuilder::new(&controller)
+            .interface_descriptor(InterfaceDescriptor {
+                interface_number: 2,
+                ..Default::default()
+            })
+            .build();
+        assert_eq!(ctrl.interface_number(), 2);
+    }
+
+    #[test]
+    pub fn last_setup_is_recorded() {
+        let controller = MockController::new();
+        let ctrl = new_ctrl(&controller, 8);
+        assert!(ctrl.last_setup().is_none());
+
+        // A vendor request: bmRequestType 0xC1, bRequest 0x42
+        setup(&ctrl, [0xc1, 0x42, 0x34, 0x12, 0x02, 0x00, 0x40, 0x00]);
+        ctrl.ctrl_setup(0);
+
+        let setup_data = ctrl.last_setup().unwrap();
+        assert_eq!(setup_data.request_code, 0x42);
+        assert_eq!(setup_data.value, 0x1234);
+        assert_eq!(setup_data.index, 0x0002);
+        assert_eq!(setup_data.length, 0x0040);
+        assert!(match setup_data.request_type.request_type() {
+            RequestType::Vendor => true,
+            _ => false,
+        });
+    }
+
+    #[test]
+    pub fn macos_hid_enumeration() {
+        let controller = MockController::new();
+        let ctrl = ClientCtrlBuilder::new(&controller)
+            .device_descriptor(DeviceDescriptor {
+                max_packet_size_ep0: 64,
+                ..Default::default()
+            })
+            .endpoint_descriptors(HID_ENDPOINTS)
+            .hid(&HID, &REPORT)
+            .build();
+
+        // After SET_ADDRESS, macOS reads the device descriptor, the configuration
+        // descriptor header and then the full configuration, asks for the device status,
+        // sets the configuration, and then fetches the HID descriptor, the interface
+        // status and the report descriptor. A stall on either GET_STATUS fails enumeration.
+        assert_eq!(
+            control_read(&ctrl, [0x80, 0x06, 0x00, 0x01, 0x00, 0x00, 0x12, 0x00]),
+            18
+        );
+        assert_eq!(
+            control_read(&ctrl, [0x80, 0x06, 0x00, 0x02, 0x00, 0x00, 0x09, 0x00]),
+            9
+        );
+        assert_eq!(
+            control_read(&ctrl, [0x80, 0x06, 0x00, 0x02, 0x00, 0x00, 0x29, 0x00]),
+            41
+        );
+
+        assert_eq!(
+            control_read(&ctrl, [0x80, 0x00, 0x00, 0x00, 0x00, 0x00, 0x02, 0x00]),
+            2
+        );
+        // Self powered, no remote wakeup
+        assert_eq!(ctrl.ctrl_buffer.buf[0].get(), 0x01);
+        assert_eq!(ctrl.ctrl_buffer.buf[1].get(), 0x00);
+
+        setup(&ctrl, [0x00, 0x09, 0x01, 0x00, 0x00, 0x00, 0x00, 0x00]);
+        assert!(match ctrl.ctrl_setup(0) {
+            CtrlSetupResult::Ok => true,
+            _ => false,
+        });
+        ctrl.ctrl_status(0);
+        ctrl.ctrl_status_complete(0);
+
+        assert_eq!(
+            control_read(&ctrl, [0x81, 0x06, 0x00, 0x21, 0x00, 0x00, 0x09, 0x00]),
+            9
+        );
+
+        assert_eq!(
+            control_read(&ctrl, [0x81, 0x00, 0x00, 0x00, 0x00, 0x00, 0x02, 0x00]),
+            2
+        );
+        assert_eq!(ctrl.ctrl_buffer.buf[0].get(), 0x00);
+        assert_eq!(ctrl.ctrl_buffer.buf[1].get(), 0x00);
+
+        assert_eq!(
+            control_read(&ctrl, [0x81, 0x06, 0x00, 0x22, 0x00, 0x00, 0x48, 0x00]),
+            8
+        );
+
+        // GET_STATUS for an interface we do not have
+        setup(&ctrl, [0x81, 0x00, 0x00, 0x00, 0x01, 0x00, 0x02, 0x00]);
+        assert!(match ctrl.ctrl_setup(0) {
+            CtrlSetupResult::ErrInvalidInterfaceIndex => true,
+            _ => false,
+        });
+    }
+
+    #[test]
+    pub fn short_device_descriptor_before_set_address() {
+        let controller = MockController::new();
+        let ctrl = new_ctrl(&controller, 8);
+
+        // Before addressing the device, Windows and Linux read only the first 8 bytes
+        // of the device descriptor to learn bMaxPacketSize0
+        setup(&ctrl, [0x80, 0x06, 0x00, 0x01, 0x00, 0x00, 0x08, 0x00]);
+        assert!(match ctrl.ctrl_setup(0) {
+            CtrlSetupResult::Ok => true,
+            _ => false,
+        });
+        assert!(match ctrl.ctrl_in(0) {
+            CtrlInResult::Packet(8, true) => true,
+            _ => false,
+        });
+        assert_eq!(ctrl.ctrl_buffer.buf[0].get(), 18);
+        assert_eq!(ctrl.ctrl_buffer.buf[1].get(), DescriptorType::Device as u8);
+        assert_eq!(ctrl.ctrl_buffer.buf[7].get(), 8);
+        ctrl.ctrl_status(0);
+        ctrl.ctrl_status_complete(0);
+
+        // SET_ADDRESS(5)
+        setup(&ctrl, [0x00, 0x05, 0x05, 0x00, 0x00, 0x00, 0x00, 0x00]);
+        assert!(match ctrl.ctrl_setup(0) {
+            CtrlSetupResult::OkSetAddress => true,
+            _ => false,
+        });
+        ctrl.ctrl_status(0);
+        ctrl.ctrl_status_complete(0);
+
+        // Then the whole descriptor, in packets of bMaxPacketSize0
+        assert_eq!(
+            control_read(&ctrl, [0x80, 0x06, 0x00, 0x01, 0x00, 0x00, 0x12, 0x00]),
+            18
+        );
+    }
+
+    #[test]
+    pub fn get_status_interface_is_zero() {
+        let controller = MockController::new();
+        let ctrl = new_ctrl(&controller, 8);
+
+        // Leave stale bytes in the buffer, to make sure both are written
+        ctrl.ctrl_buffer.buf[0].set(0xff);
+        ctrl.ctrl_buffer.buf[1].set(0xff);
+
+        // GET_STATUS(Interface 0)
+        assert_eq!(
+            control_read(&ctrl, [0x81, 0x00, 0x00, 0x00, 0x00, 0x00, 0x02, 0x00]),
+            2
+        );
+        assert_eq!(ctrl.ctrl_buffer.buf[0].get(), 0x00);
+        assert_eq!(ctrl.ctrl_buffer.buf[1].get(), 0x00);
+    }
+
+    #[test]
+    pub fn optional_serial_number() {
+        static WITH_SERIAL: &[&str] = &["XYZ Corp.", "Widget", "1234"];
+        static WITHOUT_SERIAL: &[&str] = &["XYZ Corp.", "Widget"];
+
+        for &(serial_number_string, strings) in &[(3, WITH_SERIAL), (0, WITHOUT_SERIAL)] {
+            let controller = MockController::new();
+            let ctrl = ClientCtrlBuilder::new(&controller)
+                .device_descriptor(DeviceDescriptor {
+                    manufacturer_string: 1,
+                    product_string: 2,
+                    serial_number_string,
+                    ..Default::default()
+                })
+                .strings(strings)
+                .build();
+
+            // iSerialNumber in the device descriptor
+            assert_eq!(
+                control_read(&ctrl, [0x80, 0x06, 0x00, 0x01, 0x00, 0x00, 0x12, 0x00]),
+                18
+            );
+            assert_eq!(ctrl.descriptor_storage[16].get(), serial_number_string);
+
+            // GET_DESCRIPTOR(String 3, en-US)
+            setup(&ctrl, [0x80, 0x06, 0x03, 0x03, 0x09, 0x04, 0xff, 0x00]);
+            let result = ctrl.ctrl_setup(0);
+            if serial_number_string == 0 {
+                assert!(match result {
+                    CtrlSetupResult::ErrInvalidStringIndex => true,
+                    _ => false,
+                });
+            } else {
+                assert!(match result {
+                    CtrlSetupResult::Ok => true,
+                    _ => false,
+                });
+            }
+        }
+    }
+
+    #[test]
+    pub fn serial_number_follows_strings() {
+        static STRINGS: &[&str] = &["XYZ Corp.", "Widget"];
+        let controller = MockController::new();
+        let ctrl = ClientCtrlBuilder::new(&controller)
+            .device_descriptor(DeviceDescriptor {
+                manufacturer_string: 1,
+                product_string: 2,
+                ..Default::default()
+            })
+            .strings(STRINGS)
+            .serial_number("42")
+            .build();
+
+        // iSerialNumber in the device descriptor
+        assert_eq!(
+            control_read(&ctrl, [0x80, 0x06, 0x00, 0x01, 0x00, 0x00, 0x12, 0x00]),
+            18
+        );
+        assert_eq!(ctrl.descriptor_storage[16].get(), 3);
+
+        // GET_DESCRIPTOR(String 3, en-US)
+        assert_eq!(
+            control_read(&ctrl, [0x80, 0x06, 0x03, 0x03, 0x09, 0x04, 0xff, 0x00]),
+            6
+        );
+        for (b, v) in ctrl
+            .descriptor_storage
+            .iter()
+            .zip(&[6, 0x03, b'4', 0, b'2', 0])
+        {
+            assert_eq!(b.get(), *v);
+        }
+
+        // GET_DESCRIPTOR(String 4, en-US) is beyond it
+        setup(&ctrl, [0x80, 0x06, 0x04, 0x03, 0x09, 0x04, 0xff, 0x00]);
+        assert!(match ctrl.ctrl_setup(0) {
+            CtrlSetupResult::ErrInvalidStringIndex => true,
+            _ => false,
+        });
+    }
+
+    #[test]
+    pub fn empty_serial_number_is_not_served() {
+        static STRINGS: &[&str] = &["XYZ Corp.", "Widget"];
+        let controller = MockController::new();
+        let ctrl = ClientCtrlBuilder::new(&controller)
+            .strings(STRINGS)
+            .serial_number("")
+            .build();
+
+        // No iSerialNumber in the device descriptor
+        assert_eq!(
+            control_read(&ctrl, [0x80, 0x06, 0x00, 0x01, 0x00, 0x00, 0x12, 0x00]),
+            18
+        );
+        assert_eq!(ctrl.descriptor_storage[16].get(), 0);
+
+        // GET_DESCRIPTOR(String 3, en-US) stalls rather than serving an empty string
+        setup(&ctrl, [0x80, 0x06, 0x03, 0x03, 0x09, 0x04, 0xff, 0x00]);
+        assert!(match ctrl.ctrl_setup(0) {
+            CtrlSetupResult::ErrInvalidStringIndex => true,
+            _ => false,
+        });
+    }
+
+    #[test]
+    #[should_panic]
+    pub fn builder_rejects_missing_serial_string() {
+        static WITHOUT_SERIAL: &[&str] = &["XYZ Corp.", "Widget"];
+        let controller = MockController::new();
+        ClientCtrlBuilder::new(&controller)
+            .device_descriptor(DeviceDescriptor {
+                serial_number_string: 3,
+                ..Default::default()
+            })
+            .strings(WITHOUT_SERIAL)
+            .build();
+    }
+
+    #[test]
+    pub fn string_index_out_of_range_stalls() {
+        let controller = MockController::new();
+        let ctrl = new_ctrl(&controller, 8);
+
+        // GET_DESCRIPTOR(String 99, en-US), far beyond our single string
+        setup(&ctrl, [0x80, 0x06, 0x63, 0x03, 0x09, 0x04, 0xff, 0x00]);
+        assert!(match ctrl.ctrl_setup(0) {
+            CtrlSetupResult::ErrInvalidStringIndex => true,
+            _ => false,
+        });
+
+        // GET_DESCRIPTOR(String 255, en-US)
+        setup(&ctrl, [0x80, 0x06, 0xff, 0x03, 0x09, 0x04, 0xff, 0x00]);
+        assert!(match ctrl.ctrl_setup(0) {
+            CtrlSetupResult::ErrInvalidStringIndex => true,
+            _ => false,
+        });
+    }
+
+    #[test]
+    pub fn set_configuration_applies_after_status() {
+        let controller = MockController::new();
+        let ctrl = new_ctrl(&controller, 8);
+        assert_eq!(ctrl.configuration(), 0);
+
+        // SET_CONFIGURATION(1), which has no data stage
+        setup(&ctrl, [0x00, 0x09, 0x01, 0x00, 0x00, 0x00, 0x00, 0x00]);
+        assert!(match ctrl.ctrl_setup(0) {
+            CtrlSetupResult::Ok => true,
+            _ => false,
+        });
+        assert_eq!(ctrl.configuration(), 0);
+
+        ctrl.ctrl_status(0);
+        assert_eq!(ctrl.configuration(), 0);
+
+        ctrl.ctrl_status_complete(0);
+        assert_eq!(ctrl.configuration(), 1);
+
+        // A later transfer must not apply it again
+        ctrl.ctrl_status_complete(0);
+        assert_eq!(ctrl.configuration(), 1);
+
+        // GET_CONFIGURATION
+        assert_eq!(
+            control_read(&ctrl, [0x80, 0x08, 0x00, 0x00, 0x00, 0x00, 0x01, 0x00]),
+            1
+        );
+        assert_eq!(ctrl.ctrl_buffer.buf[0].get(), 1);
+
+        // A bus reset leaves the device unconfigured
+        ctrl.bus_reset();
+        assert_eq!(ctrl.configuration(), 0);
+    }
+
+    #[test]
+    pub fn speed_is_reported_after_enable() {
+        let controller = MockController::new();
+        let ctrl = new_ctrl(&controller, 8);
+        assert_eq!(ctrl.speed(), None);
+
+        ctrl.enable();
+        assert_eq!(controller.speed.get(), Some(DeviceSpeed::Full));
+        assert_eq!(ctrl.speed(), Some(DeviceSpeed::Full));
+    }
+
+    #[test]
+    pub fn attach_requires_enable() {
+        let controller = MockController::new();
+        let ctrl = new_ctrl(&controller, 8);
+
+        assert_eq!(ctrl.attach(), ReturnCode::EOFF);
+        assert!(!controller.attached.get());
+
+        ctrl.enable();
+        assert_eq!(ctrl.attach(), ReturnCode::SUCCESS);
+        assert!(controller.attached.get());
+    }
+
+    #[test]
+    pub fn setup_aborts_transfer_in_progress() {
+        let controller = MockController::new();
+        let ctrl = new_ctrl(&controller, 8);
+
+        // GET_DESCRIPTOR(String 1, en-US), whose reply takes several packets
+        setup(&ctrl, [0x80, 0x06, 0x01, 0x03, 0x09, 0x04, 0xff, 0x00]);
+        assert!(match ctrl.ctrl_setup(0) {
+            CtrlSetupResult::Ok => true,
+            _ => false,
+        });
+        assert!(match ctrl.ctrl_in(0) {
+            CtrlInResult::Packet(8, false) => true,
+            _ => false,
+        });
+
+        // The host gives up and sends a request we stall: nothing is left to send
+        setup(&ctrl, [0x80, 0x06, 0x63, 0x03, 0x09, 0x04, 0xff, 0x00]);
+        assert!(match ctrl.ctrl_setup(0) {
+            CtrlSetupResult::ErrInvalidStringIndex => true,
+            _ => false,
+        });
+        assert!(match ctrl.ctrl_in(0) {
+            CtrlInResult::Error => true,
+            _ => false,
+        });
+
+        // A new request mid-transfer starts over from its own first byte
+        setup(&ctrl, [0x80, 0x06, 0x01, 0x03, 0x09, 0x04, 0xff, 0x00]);
+        ctrl.ctrl_setup(0);
+        ctrl.ctrl_in(0);
+        setup(&ctrl, [0x80, 0x06, 0x00, 0x01, 0x00, 0x00, 0x12, 0x00]);
+        assert!(match ctrl.ctrl_setup(0) {
+            CtrlSetupResult::Ok => true,
+            _ => false,
+        });
+        assert!(match ctrl.ctrl_in(0) {
+            CtrlInResult::Packet(8, false) => true,
+            _ => false,
+        });
+        assert_eq!(ctrl.ctrl_buffer.buf[0].get(), 18);
+        assert_eq!(ctrl.ctrl_buffer.buf[1].get(), DescriptorType::Device as u8);
+    }
+
+    #[test]
+    pub fn remote_wakeup_feature() {
+        let controller = MockController::new();
+        let ctrl = ClientCtrlBuilder::new(&controller)
+            .configuration_descriptor(ConfigurationDescriptor {
+                attributes: ConfigurationAttributes::new(false, true),
+                ..Default::default()
+            })
+            .build();
+        assert!(!ctrl.remote_wakeup_enabled());
+
+        // SET_FEATURE(DEVICE_REMOTE_WAKEUP)
+        setup(&ctrl, [0x00, 0x03, 0x01, 0x00, 0x00, 0x00, 0x00, 0x00]);
+        assert!(match ctrl.ctrl_setup(0) {
+            CtrlSetupResult::Ok => true,
+            _ => false,
+        });
+        assert!(ctrl.remote_wakeup_enabled());
+
+        // A bus reset disables it again
+        ctrl.bus_reset();
+        assert!(!ctrl.remote_wakeup_enabled());
+        setup(&ctrl, [0x00, 0x03, 0x01, 0x00, 0x00, 0x00, 0x00, 0x00]);
+        ctrl.ctrl_setup(0);
+        assert!(ctrl.remote_wakeup_enabled());
+
+        // GET_STATUS(Device): bus powered, remote wakeup enabled
+        assert_eq!(
+            control_read(&ctrl, [0x80, 0x00, 0x00, 0x00, 0x00, 0x00, 0x02, 0x00]),
+            2
+        );
+        assert_eq!(ctrl.ctrl_buffer.buf[0].get(), 0x02);
+
+        // CLEAR_FEATURE(DEVICE_REMOTE_WAKEUP)
+        setup(&ctrl, [0x00, 0x01, 0x01, 0x00, 0x00, 0x00, 0x00, 0x00]);
+        assert!(match ctrl.ctrl_setup(0) {
+            CtrlSetupResult::Ok => true,
+            _ => false,
+        });
+        assert!(!ctrl.remote_wakeup_enabled());
+    }
+
+    #[test]
+    pub fn remote_wakeup_requires_support() {
+        let controller = MockController::new();
+        let ctrl = new_ctrl(&controller, 8);
+
+        // SET_FEATURE(DEVICE_REMOTE_WAKEUP) on a device that does not declare it
+        setup(&ctrl, [0x00, 0x03, 0x01, 0x00, 0x00, 0x00, 0x00, 0x00]);
+        assert!(match ctrl.ctrl_setup(0) {
+            CtrlSetupResult::ErrGeneric => true,
+            _ => false,
+        });
+        assert!(!ctrl.remote_wakeup_enabled());
+    }
+
+    #[test]
+    pub fn ss_endpoint_companions_follow_their_endpoints() {
+        static COMPANIONS: &[SsEndpointCompanionDescriptor] = &[
+            SsEndpointCompanionDescriptor {
+                max_burst: 0,
+                attributes: 0,
+                bytes_per_interval: 64,
+            },
+            SsEndpointCompanionDescriptor {
+                max_burst: 0,
+                attributes: 0,
+                bytes_per_interval: 64,
+            },
+        ];
+
+        let controller = MockController::new();
+        let ctrl = ClientCtrlBuilder::new(&controller)
+            .device_descriptor(DeviceDescriptor {
+                max_packet_size_ep0: 64,
+                ..Default::default()
+            })
+            .endpoint_descriptors(HID_ENDPOINTS)
+            .ss_endpoint_companions(COMPANIONS)
+            .build();
+
+        // Configuration, interface, and two endpoints with their companions
+        let total_len = 9 + 9 + 2 * (7 + 6);
+        assert_eq!(
+            control_read(&ctrl, [0x80, 0x06, 0x00, 0x02, 0x00, 0x00, 0xff, 0x00]),
+            total_len
+        );
+        let buf = &ctrl.ctrl_buffer.buf;
+        assert_eq!(buf[2].get() as usize, total_len);
+        for &offset in &[18, 31] {
+            assert_eq!(buf[offset + 1].get(), DescriptorType::Endpoint as u8);
+            assert_eq!(buf[offset + 7].get(), 6);
+            assert_eq!(
+                buf[offset + 8].get(),
+                DescriptorType::SsEndpointCompanion as u8
+            );
+            assert_eq!(buf[offset + 11].get(), 64);
+        }
+    }
+
+    #[test]
+    #[should_panic]
+    pub fn builder_rejects_missing_ss_endpoint_companion() {
+        static COMPANIONS: &[SsEndpointCompanionDescriptor] = &[SsEndpointCompanionDescriptor {
+            max_burst: 0,
+            attributes: 0,
+            bytes_per_interval: 0,
+        }];
+
+        let controller = MockController::new();
+        ClientCtrlBuilder::new(&controller)
+            .endpoint_descriptors(HID_ENDPOINTS)
+            .ss_endpoint_companions(COMPANIONS)
+            .build();
+    }
+
+    struct RecordingStallClient {
+        request_code: Cell<Option<u8>>,
+    }
+
+    impl StallClient for RecordingStallClient {
+        fn ctrl_stalled(&self, setup_data: SetupData, result: &CtrlSetupResult) {
+            assert!(match result {
+                CtrlSetupResult::Ok | CtrlSetupResult::OkSetAddress => false,
+                _ => true,
+            });
+            self.request_code.set(Some(setup_data.request_code));
+        }
+    }
+
+    #[test]
+    pub fn stall_client_sees_unsupported_requests() {
+        let stall_client = RecordingStallClient {
+            request_code: Cell::new(None),
+        };
+        let controller = MockController::new();
+        let ctrl = new_ctrl(&controller, 8);
+        ctrl.set_stall_client(&stall_client);
+
+        // GET_DESCRIPTOR(Device) succeeds
+        setup(&ctrl, [0x80, 0x06, 0x00, 0x01, 0x00, 0x00, 0x12, 0x00]);
+        ctrl.ctrl_setup(0);
+        assert_eq!(stall_client.request_code.get(), None);
+
+        // SYNCH_FRAME is not supported
+        setup(&ctrl, [0x82, 0x0c, 0x00, 0x00, 0x01, 0x00, 0x02, 0x00]);
+        ctrl.ctrl_setup(0);
+        assert_eq!(stall_client.request_code.get(), Some(0x0c));
+    }
+
+    #[test]
+    pub fn class_requests_go_to_hook() {
+        let stall_client = RecordingStallClient {
+            request_code: Cell::new(None),
+        };
+        let controller = MockController::new();
+        let ctrl = new_ctrl(&controller, 8);
+        ctrl.set_stall_client(&stall_client);
+        let hook = |setup_data: SetupData| match setup_data.request_code {
+            0x01 => Some(ctrl.ctrl_in_reply(0, &[0x42], 1)),
+            _ => None,
+        };
+
+        // A class request the hook answers
+        setup(&ctrl, [0xa1, 0x01, 0x00, 0x01, 0x00, 0x00, 0x01, 0x00]);
+        assert!(match ctrl.ctrl_setup_with(0, hook) {
+            CtrlSetupResult::Ok => true,
+            _ => false,
+        });
+
+        // One it does not answer aborts that transfer, and is stalled and reported
+        setup(&ctrl, [0xa1, 0x03, 0x00, 0x00, 0x00, 0x00, 0x01, 0x00]);
+        assert!(match ctrl.ctrl_setup_with(0, hook) {
+            CtrlSetupResult::ErrUnrecognizedRequestType => true,
+            _ => false,
+        });
+        assert_eq!(stall_client.request_code.get(), Some(0x03));
+        assert!(match ctrl.ctrl_in(0) {
+            CtrlInResult::Error => true,
+            _ => false,
+        });
+
+        // Without a hook, class requests are stalled rather than mistaken for vendor data
+        setup(&ctrl, [0xa1, 0x01, 0x00, 0x01, 0x00, 0x00, 0x01, 0x00]);
+        assert!(match ctrl.ctrl_setup(0) {
+            CtrlSetupResult::ErrUnrecognizedRequestType => true,
+            _ => false,
+        });
+    }
+
+    /// Marks the device descriptor as a composite device
+    struct CompositeFilter {
+        filtered_len: Cell<usize>,
+    }
+
+    impl DescriptorFilter for CompositeFilter {
+        fn filter_descriptor(&self, descriptor_type: DescriptorType, descriptor: &[Cell<u8>]) {
+            if let DescriptorType::Device = descriptor_type {
+                // bDeviceClass: Miscellaneous
+                descriptor[4].set(0xef);
+                self.filtered_len.set(descriptor.len());
+            }
+        }
+    }
+
+    #[test]
+    pub fn descriptor_filter_patches_served_descriptor() {
+        let filter = CompositeFilter {
+            filtered_len: Cell::new(0),
+        };
+        let controller = MockController::new();
+        let ctrl = new_ctrl(&controller, 8);
+
+        // Without a filter the descriptor is served as built
+        control_read(&ctrl, [0x80, 0x06, 0x00, 0x01, 0x00, 0x00, 0x08, 0x00]);
+        assert_eq!(ctrl.ctrl_buffer.buf[4].get(), 0x00);
+
+        ctrl.set_descriptor_filter(&filter);
+        control_read(&ctrl, [0x80, 0x06, 0x00, 0x01, 0x00, 0x00, 0x08, 0x00]);
+        assert_eq!(ctrl.ctrl_buffer.buf[4].get(), 0xef);
+
+        // The filter sees the whole descriptor, even for a short read
+        assert_eq!(filter.filtered_len.get(), 18);
+
+        // Other descriptors are left alone
+        filter.filtered_len.set(0);
+        control_read(&ctrl, [0x80, 0x06, 0x00, 0x02, 0x00, 0x00, 0x09, 0x00]);
+        assert_eq!(filter.filtered_len.get(), 0);
+    }
+
+    #[test]
+    pub fn interface_string() {
+        static INTERFACE_STRINGS: &[&str] = &["XYZ Corp.", "Widget", "Debug"];
+
+        let controller = MockController::new();
+        let ctrl = ClientCtrlBuilder::new(&controller)
+            .device_descriptor(DeviceDescriptor {
+                max_packet_size_ep0: 64,
+                ..Default::default()
+            })
+            .interface_descriptor(InterfaceDescriptor {
+                string_index: 3,
+                ..Default::default()
+            })
+            .strings(INTERFACE_STRINGS)
+            .build();
+
+        // iInterface, in the interface descriptor that follows the configuration descriptor
+        assert_eq!(
+            control_read(&ctrl, [0x80, 0x06, 0x00, 0x02, 0x00, 0x00, 0xff, 0x00]),
+            18
+        );
+        assert_eq!(ctrl.ctrl_buffer.buf[9 + 8].get(), 3);
+
+        // GET_DESCRIPTOR(String 3, en-US) resolves to the interface name
+        assert_eq!(
+            control_read(&ctrl, [0x80, 0x06, 0x03, 0x03, 0x09, 0x04, 0xff, 0x00]),
+            2 + 2 * "Debug".len()
+        );
+        assert_eq!(ctrl.ctrl_buffer.buf[2].get(), b'D');
+    }
+
+    #[test]
+    #[should_panic]
+    pub fn builder_rejects_missing_interface_string() {
+        let controller = MockController::new();
+        ClientCtrlBuilder::new(&controller)
+            .interface_descriptor(InterfaceDescriptor {
+                string_index: 2,
+                ..Default::default()
+            })
+            .strings(STRINGS)
+            .build();
+    }
+
+    #[test]
+    pub fn dump_state_prints_everything() {
+        let log = MockLogger::new();
+        let controller = MockController::new();
+        let ctrl = new_ctrl(&controller, 8);
+        ctrl.set_logger(&log);
+
+        ctrl.dump_state();
+        assert_eq!(
+            log.take(),
+            "usb_ctrl: interface=0 configuration=0 speed=None remote_wakeup=false last_setup=None\n\
+             usb_ctrl: endpoint 0 state=Init\n\
+             usb_ctrl: endpoint 1 state=Init\n\
+             usb_ctrl: endpoint 2 state=Init\n"
+        );
+
+        // SET_CONFIGURATION(1), stopped before its Status stage completes
+        ctrl.enable();
+        setup(&ctrl, [0x00, 0x09, 0x01, 0x00, 0x00, 0x00, 0x00, 0x00]);
+        ctrl.ctrl_setup(0);
+        ctrl.dump_state();
+        let dump = log.take();
+        assert!(dump.starts_with(
+            "usb_ctrl: interface=0 configuration=0 speed=Some(Full) remote_wakeup=false last_setup=Some("
+        ));
+        assert!(dump.contains("usb_ctrl: endpoint 0 state=SetConfiguration(1)\n"));
+    }
+}
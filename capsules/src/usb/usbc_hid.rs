@@ -0,0 +1,419 @@
+//! Input report state and class requests shared by the HID capsules
+//!
+//! A HID capsule that sends input reports on a single interrupt IN endpoint
+//! keeps a `HidReports`, tells it whenever its state changes, and hands it
+//! the current report from its `Client` callbacks.  `HidReports` decides when
+//! a report is sent, and answers the GET_REPORT, GET_IDLE and SET_IDLE class
+//! requests.
+
+use super::descriptors::HIDReportType;
+use super::descriptors::HIDRequest;
+use super::usbc_client_ctrl::{self, ClientCtrl, Logger};
+use core::cell::Cell;
+use core::fmt;
+use kernel::common::cells::{OptionalCell, VolatileCell};
+use kernel::hil;
+use kernel::hil::usb::TransferType;
+
+/// The largest input report, which must fit in a single packet
+const MAX_REPORT_SIZE: usize = 8;
+
+pub struct HidReports<'a> {
+    // Prefix for log messages, naming the capsule
+    name: &'static str,
+
+    // The interrupt IN endpoint carrying input reports
+    endpoint: usize,
+
+    // Whether the state has changed since it was last reported to the host
+    report_pending: Cell<bool>,
+
+    // The idle rate set by the host, in units of 4 ms, or 0 for indefinite
+    idle_rate: Cell<u8>,
+
+    // Whether to skip reports identical to the last one sent
+    coalesce: Cell<bool>,
+
+    // The last report sent on the interrupt endpoint, if any since the last bus reset
+    last_report: Cell<Option<[u8; MAX_REPORT_SIZE]>>,
+
+    // Given our diagnostics instead of `debug!`
+    logger: OptionalCell<&'a dyn Logger>,
+}
+
+impl<'a> HidReports<'a> {
+    pub fn new(name: &'static str, endpoint: usize) -> Self {
+        HidReports {
+            name,
+            endpoint,
+            report_pending: Cell::new(false),
+            idle_rate: Cell::new(0),
+            coalesce: Cell::new(false),
+            last_report: Cell::new(None),
+            logger: OptionalCell::empty(),
+        }
+    }
+
+    /// Note that the report has changed, so that the next IN transaction sends it
+    ///
+    /// The caller must still resume the endpoint for the controller to ask.
+    pub fn report_changed(&self) {
+        self.report_pending.set(true);
+    }
+
+    /// The idle rate the host set for the report with the given ID, in units of 4 ms
+    ///
+    /// Our reports have no ID, so only ID 0 has an idle rate.  Zero means
+    /// indefinite, i.e. report only on change, which is what we always do.
+    pub fn hid_idle_rate(&self, report_id: u8) -> Option<u8> {
+        if report_id == 0 {
+            Some(self.idle_rate.get())
+        } else {
+            None
+        }
+    }
+
+    /// Skip sending a report when it is identical to the last one sent
+    ///
+    /// This saves bus bandwidth when the state is set repeatedly to the same
+    /// value.  It is off by default.
+    pub fn set_coalesce_reports(&self, coalesce: bool) {
+        self.coalesce.set(coalesce);
+    }
+
+    /// Register a logger to receive our diagnostics instead of `debug!`
+    pub fn set_logger(&self, logger: &'a dyn Logger) {
+        self.logger.set(logger);
+    }
+
+    pub fn bus_reset(&self) {
+        // The host will ask for the current state once it has configured us again
+        self.report_pending.set(false);
+        self.last_report.set(None);
+    }
+
+    /// Handle a Control Setup transaction, where `report` is the current input report
+    pub fn ctrl_setup<'b, C: hil::usb::UsbController<'b>>(
+        &self,
+        client_ctrl: &'b ClientCtrl<'b, '_, C>,
+        endpoint: usize,
+        report: &[u8],
+    ) -> hil::usb::CtrlSetupResult {
+        let interface = client_ctrl.interface_number() as u16;
+        client_ctrl.ctrl_setup_with(endpoint, |setup_data| {
+            match setup_data.get_hid_class_request() {
+                Some(HIDRequest::GetReport {
+                    report_type: HIDReportType::Input,
+                    interface: i,
+                    requested_length,
+                    ..
+                }) if i == interface => {
+                    // The host may poll our state instead of waiting for an
+                    // interrupt report, e.g. after setting an indefinite idle rate
+                    Some(client_ctrl.ctrl_in_reply(endpoint, report, requested_length))
+                }
+                Some(HIDRequest::SetIdle {
+                    duration,
+                    report_id: 0,
+                    interface: i,
+                }) if i == interface => {
+                    self.idle_rate.set(duration);
+                    Some(hil::usb::CtrlSetupResult::Ok)
+                }
+                Some(HIDRequest::GetIdle {
+                    report_id: 0,
+                    interface: i,
+                }) if i == interface => {
+                    Some(client_ctrl.ctrl_in_reply(endpoint, &[self.idle_rate.get()], 1))
+                }
+                _ => None,
+            }
+        })
+    }
+
+    /// Handle a Bulk/Interrupt IN transaction, copying `report` to `buffer` if it is to be sent
+    ///
+    /// A report longer than `buffer` or than `MAX_REPORT_SIZE` is never sent;
+    /// the transaction is answered with an error instead.
+    pub fn packet_in(
+        &self,
+        transfer_type: TransferType,
+        endpoint: usize,
+        buffer: &[VolatileCell<u8>],
+        report: &[u8],
+    ) -> hil::usb::InResult {
+        match transfer_type {
+            TransferType::Interrupt if endpoint == self.endpoint => {
+                if report.len() > MAX_REPORT_SIZE || report.len() > buffer.len() {
+                    self.log(format_args!(
+                        "{}: report of {} bytes does not fit in a packet",
+                        self.name,
+                        report.len()
+                    ));
+                    return hil::usb::InResult::Error;
+                }
+                let mut padded = [0; MAX_REPORT_SIZE];
+                padded[..report.len()].copy_from_slice(report);
+                let duplicate = self.coalesce.get() && self.last_report.get() == Some(padded);
+                if self.report_pending.take() && !duplicate {
+                    for (i, b) in report.iter().enumerate() {
+                        buffer[i].set(*b);
+                    }
+                    self.last_report.set(Some(padded));
+                    hil::usb::InResult::Packet(report.len())
+                } else {
+                    // Nothing has changed
+                    hil::usb::InResult::Delay
+                }
+            }
+            TransferType::Interrupt => {
+                self.log(format_args!(
+                    "{}: packet_in on unknown endpoint {}",
+                    self.name, endpoint
+                ));
+                hil::usb::InResult::Error
+            }
+            _ => {
+                self.log(format_args!(
+                    "{}: packet_in({}) is not an interrupt transfer",
+                    self.name, endpoint
+                ));
+                hil::usb::InResult::Error
+            }
+        }
+    }
+
+    /// Handle a Bulk/Interrupt OUT transaction
+    pub fn packet_out(&self, endpoint: usize) -> hil::usb::OutResult {
+        // We have no OUT endpoint
+        self.log(format_args!(
+            "{}: packet_out on IN-only device, endpoint {}",
+            self.name, endpoint
+        ));
+        hil::usb::OutResult::Error
+    }
+
+    fn log(&self, args: fmt::Arguments) {
+        usbc_client_ctrl::log(&self.logger, args);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::super::descriptors::{
+        HIDCountryCode, HIDDescriptor, InterfaceDescriptor, ReportDescriptor,
+    };
+    use super::super::mock::{MockController, MockLogger};
+    use super::super::usbc_client_ctrl::{ClientCtrl, ClientCtrlBuilder};
+    use super::HidReports;
+    use kernel::common::cells::VolatileCell;
+    use kernel::hil::usb::{CtrlInResult, CtrlSetupResult, InResult, OutResult, TransferType};
+
+    const ENDPOINT_NUM: usize = 1;
+
+    static REPORT: ReportDescriptor<'static> = ReportDescriptor { desc: &[] };
+
+    static HID: HIDDescriptor<'static> = HIDDescriptor {
+        hid_class: 0x0111,
+        country_code: HIDCountryCode::NotSupported,
+        sub_descriptors: &[],
+    };
+
+    fn new_ctrl(controller: &'a MockController<'a>) -> ClientCtrl<'a, 'static, MockController<'a>> {
+        ClientCtrlBuilder::new(controller)
+            .interface_descriptor(InterfaceDescriptor {
+                interface_class: 0x03, // HID
+                ..Default::default()
+            })
+            .hid(&HID, &REPORT)
+            .build()
+    }
+
+    /// Run an IN transaction, returning the length of the report sent if any
+    fn report(hid: &HidReports, buffer: &[VolatileCell<u8>], report: &[u8]) -> Option<usize> {
+        match hid.packet_in(TransferType::Interrupt, ENDPOINT_NUM, buffer, report) {
+            InResult::Packet(n) => Some(n),
+            _ => None,
+        }
+    }
+
+    #[test]
+    pub fn reports_on_change() {
+        let log = MockLogger::new();
+        let hid = HidReports::new("hid_test", ENDPOINT_NUM);
+        hid.set_logger(&log);
+        let buffer: [VolatileCell<u8>; 8] = Default::default();
+
+        // Nothing to send until the report changes, and then only once
+        assert_eq!(report(&hid, &buffer, &[1, 2]), None);
+        hid.report_changed();
+        assert_eq!(report(&hid, &buffer, &[1, 2]), Some(2));
+        assert_eq!([buffer[0].get(), buffer[1].get()], [1, 2]);
+        assert_eq!(report(&hid, &buffer, &[1, 2]), None);
+
+        // A bus reset drops the pending report
+        hid.report_changed();
+        hid.bus_reset();
+        assert_eq!(report(&hid, &buffer, &[3, 4]), None);
+
+        // Only the declared interrupt endpoint carries reports
+        hid.report_changed();
+        assert!(
+            match hid.packet_in(TransferType::Interrupt, 2, &buffer, &[3, 4]) {
+                InResult::Error => true,
+                _ => false,
+            }
+        );
+        assert_eq!(log.take(), "hid_test: packet_in on unknown endpoint 2\n");
+        assert!(
+            match hid.packet_in(TransferType::Bulk, ENDPOINT_NUM, &buffer, &[3, 4]) {
+                InResult::Error => true,
+                _ => false,
+            }
+        );
+        assert_eq!(
+            log.take(),
+            "hid_test: packet_in(1) is not an interrupt transfer\n"
+        );
+        assert!(match hid.packet_out(ENDPOINT_NUM) {
+            OutResult::Error => true,
+            _ => false,
+        });
+        assert_eq!(
+            log.take(),
+            "hid_test: packet_out on IN-only device, endpoint 1\n"
+        );
+    }
+
+    #[test]
+    pub fn rejects_oversize_reports() {
+        let log = MockLogger::new();
+        let hid = HidReports::new("hid_test", ENDPOINT_NUM);
+        hid.set_logger(&log);
+        let buffer: [VolatileCell<u8>; 8] = Default::default();
+
+        hid.report_changed();
+        assert!(
+            match hid.packet_in(TransferType::Interrupt, ENDPOINT_NUM, &buffer, &[0; 9]) {
+                InResult::Error => true,
+                _ => false,
+            }
+        );
+        assert_eq!(
+            log.take(),
+            "hid_test: report of 9 bytes does not fit in a packet\n"
+        );
+
+        // A report that fits the size limit but not the endpoint buffer
+        let small: [VolatileCell<u8>; 2] = Default::default();
+        assert!(
+            match hid.packet_in(TransferType::Interrupt, ENDPOINT_NUM, &small, &[1, 2, 3]) {
+                InResult::Error => true,
+                _ => false,
+            }
+        );
+        assert_eq!(
+            log.take(),
+            "hid_test: report of 3 bytes does not fit in a packet\n"
+        );
+
+        // The pending report is still sent once it fits
+        assert_eq!(report(&hid, &buffer, &[1, 2, 3]), Some(3));
+    }
+
+    #[test]
+    pub fn get_report() {
+        let controller = MockController::new();
+        let ctrl = new_ctrl(&controller);
+        let hid = HidReports::new("hid_test", ENDPOINT_NUM);
+        ctrl.enable();
+
+        // GET_REPORT(Input, ID 0) on interface 0
+        controller.setup([0xa1, 0x01, 0x00, 0x01, 0x00, 0x00, 0x08, 0x00]);
+        assert!(match hid.ctrl_setup(&ctrl, 0, &[5, 6, 7]) {
+            CtrlSetupResult::Ok => true,
+            _ => false,
+        });
+        assert!(match ctrl.ctrl_in(0) {
+            CtrlInResult::Packet(3, true) => true,
+            _ => false,
+        });
+        let buf = controller.buffer(0);
+        assert_eq!([buf[0].get(), buf[1].get(), buf[2].get()], [5, 6, 7]);
+
+        // GET_REPORT on another interface is not ours to answer
+        controller.setup([0xa1, 0x01, 0x00, 0x01, 0x01, 0x00, 0x08, 0x00]);
+        assert!(match hid.ctrl_setup(&ctrl, 0, &[5, 6, 7]) {
+            CtrlSetupResult::ErrUnrecognizedRequestType => true,
+            _ => false,
+        });
+    }
+
+    #[test]
+    pub fn coalesced_reports() {
+        let hid = HidReports::new("hid_test", ENDPOINT_NUM);
+        let buffer: [VolatileCell<u8>; 8] = Default::default();
+
+        // Off by default, so an unchanged report is sent again
+        hid.report_changed();
+        assert_eq!(report(&hid, &buffer, &[1, 2]), Some(2));
+        hid.report_changed();
+        assert_eq!(report(&hid, &buffer, &[1, 2]), Some(2));
+
+        hid.set_coalesce_reports(true);
+        hid.report_changed();
+        assert_eq!(report(&hid, &buffer, &[1, 2]), None);
+
+        // Any byte that differs makes a new report
+        hid.report_changed();
+        assert_eq!(report(&hid, &buffer, &[1, 3]), Some(2));
+
+        // The host has seen nothing since a bus reset, so the same report goes out again
+        hid.bus_reset();
+        hid.report_changed();
+        assert_eq!(report(&hid, &buffer, &[1, 3]), Some(2));
+
+        hid.set_coalesce_reports(false);
+        hid.report_changed();
+        assert_eq!(report(&hid, &buffer, &[1, 3]), Some(2));
+    }
+
+    #[test]
+    pub fn idle_rate() {
+        let controller = MockController::new();
+        let ctrl = new_ctrl(&controller);
+        let hid = HidReports::new("hid_test", ENDPOINT_NUM);
+        ctrl.enable();
+        assert_eq!(hid.hid_idle_rate(0), Some(0));
+
+        // SET_IDLE(500 ms, all reports) on interface 0
+        controller.setup([0x21, 0x0a, 0x00, 0x7d, 0x00, 0x00, 0x00, 0x00]);
+        assert!(match hid.ctrl_setup(&ctrl, 0, &[]) {
+            CtrlSetupResult::Ok => true,
+            _ => false,
+        });
+        ctrl.ctrl_status_complete(0);
+        assert_eq!(hid.hid_idle_rate(0), Some(0x7d));
+
+        // Our reports have no ID, so no other report has an idle rate
+        assert_eq!(hid.hid_idle_rate(1), None);
+        controller.setup([0x21, 0x0a, 0x01, 0x10, 0x00, 0x00, 0x00, 0x00]);
+        assert!(match hid.ctrl_setup(&ctrl, 0, &[]) {
+            CtrlSetupResult::ErrUnrecognizedRequestType => true,
+            _ => false,
+        });
+        assert_eq!(hid.hid_idle_rate(0), Some(0x7d));
+
+        // GET_IDLE(all reports) on interface 0
+        controller.setup([0xa1, 0x02, 0x00, 0x00, 0x00, 0x00, 0x01, 0x00]);
+        assert!(match hid.ctrl_setup(&ctrl, 0, &[]) {
+            CtrlSetupResult::Ok => true,
+            _ => false,
+        });
+        assert!(match ctrl.ctrl_in(0) {
+            CtrlInResult::Packet(1, true) => true,
+            _ => false,
+        });
+        assert_eq!(controller.buffer(0)[0].get(), 0x7d);
+    }
+}
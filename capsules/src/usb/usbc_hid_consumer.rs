@@ -0,0 +1,345 @@
+//! A USB HID consumer-control device, for media keys
+//!
+//! It declares a single interrupt IN endpoint, on which it reports the usage
+//! (from the Consumer usage page, 0x0C) of the key currently pressed, or zero
+//! when no key is pressed.  For example, Play/Pause is usage 0xCD and Volume
+//! Increment is usage 0xE9.
+//!
+//! Usage
+//! -----
+//!
+//! ```rust
+//! let consumer = static_init!(
+//!     capsules::usb::usbc_hid_consumer::ConsumerControl<'static, sam4l::usbc::Usbc<'static>>,
+//!     capsules::usb::usbc_hid_consumer::ConsumerControl::new(&sam4l::usbc::USBC));
+//! sam4l::usbc::USBC.set_client(consumer);
+//!
+//! consumer.enable();
+//! consumer.attach();
+//! consumer.press_consumer(0xCD); // Play/Pause
+//! consumer.release_consumer();
+//! ```
+
+use super::descriptors::Buffer8;
+use super::descriptors::DescriptorType;
+use super::descriptors::DeviceDescriptor;
+use super::descriptors::EndpointAddress;
+use super::descriptors::EndpointDescriptor;
+use super::descriptors::HIDCountryCode;
+use super::descriptors::HIDDescriptor;
+use super::descriptors::HIDSubordinateDescriptor;
+use super::descriptors::InterfaceDescriptor;
+use super::descriptors::ReportDescriptor;
+use super::descriptors::TransferDirection;
+use super::usbc_client_ctrl::ClientCtrl;
+use super::usbc_client_ctrl::ClientCtrlBuilder;
+use super::usbc_client_ctrl::Logger;
+use super::usbc_hid::HidReports;
+use core::cell::Cell;
+use kernel::hil;
+use kernel::hil::usb::TransferType;
+use kernel::ReturnCode;
+
+const VENDOR_ID: u16 = 0x6667;
+const PRODUCT_ID: u16 = 0xabce;
+
+static STRINGS: &[&str] = &[
+    "XYZ Corp.",    // Manufacturer
+    "Media Keys",   // Product
+    "Serial No. 5", // Serial number
+];
+
+/// The interrupt IN endpoint carrying input reports
+const ENDPOINT_NUM: usize = 1;
+
+/// Size of an input report: a single 16-bit usage
+const REPORT_SIZE: usize = 2;
+
+static ENDPOINTS: &[EndpointDescriptor] = &[EndpointDescriptor {
+    endpoint_address: EndpointAddress::new_const(ENDPOINT_NUM, TransferDirection::DeviceToHost),
+    transfer_type: TransferType::Interrupt,
+    max_packet_size: 8,
+    interval: 10,
+}];
+
+const REPORT_DESCRIPTOR: &[u8] = &hid_report! {
+    usage_page(0x0C), // Consumer
+    usage(0x01),      // Consumer Control
+    collection(application) {
+        logical_minimum(0x00),
+        logical_maximum(0x03FF as u16),
+        usage_minimum(0x00),
+        usage_maximum(0x03FF as u16),
+        report_size(16),
+        report_count(1),
+        input(0x00), // Data, Array, Absolute
+    },
+};
+
+static REPORT: ReportDescriptor<'static> = ReportDescriptor {
+    desc: REPORT_DESCRIPTOR,
+};
+
+static SUB_HID_DESCRIPTORS: &[HIDSubordinateDescriptor] = &[HIDSubordinateDescriptor {
+    typ: DescriptorType::Report,
+    len: REPORT_DESCRIPTOR.len() as u16,
+}];
+
+static HID: HIDDescriptor<'static> = HIDDescriptor {
+    hid_class: 0x0111,
+    country_code: HIDCountryCode::NotSupported,
+    sub_descriptors: SUB_HID_DESCRIPTORS,
+};
+
+pub struct ConsumerControl<'a, C: 'a> {
+    client_ctrl: ClientCtrl<'a, 'static, C>,
+
+    // Buffer for the interrupt IN endpoint
+    buffer: Buffer8,
+
+    // The usage currently pressed, or zero for none
+    usage: Cell<u16>,
+
+    // When to send reports, and the HID class requests
+    hid: HidReports<'a>,
+}
+
+impl<'a, C: hil::usb::UsbController<'a>> ConsumerControl<'a, C> {
+    pub fn new(controller: &'a C) -> Self {
+        ConsumerControl {
+            client_ctrl: ClientCtrlBuilder::new(controller)
+                .device_descriptor(DeviceDescriptor {
+                    vendor_id: VENDOR_ID,
+                    product_id: PRODUCT_ID,
+                    manufacturer_string: 1,
+                    product_string: 2,
+                    serial_number_string: 3,
+                    ..Default::default()
+                })
+                .interface_descriptor(InterfaceDescriptor {
+                    interface_class: 0x03, // HID
+                    interface_subclass: 0x00,
+                    interface_protocol: 0x00,
+                    ..Default::default()
+                })
+                .endpoint_descriptors(ENDPOINTS)
+                .hid(&HID, &REPORT)
+                .strings(STRINGS)
+                .build(),
+            buffer: Default::default(),
+            usage: Cell::new(0),
+            hid: HidReports::new("hid_consumer", ENDPOINT_NUM),
+        }
+    }
+
+    /// Report that the key with the given Consumer page usage is pressed
+    pub fn press_consumer(&'a self, usage: u16) {
+        self.set_usage(usage);
+    }
+
+    /// Report that no key is pressed
+    pub fn release_consumer(&'a self) {
+        self.set_usage(0);
+    }
+
+    /// The idle rate the host set for the report with the given ID, in units of 4 ms
+    pub fn hid_idle_rate(&self, report_id: u8) -> Option<u8> {
+        self.hid.hid_idle_rate(report_id)
+    }
+
+    /// Skip sending a report when it is identical to the last one sent
+    pub fn set_coalesce_reports(&self, coalesce: bool) {
+        self.hid.set_coalesce_reports(coalesce);
+    }
+
+    /// The input report for the current state: the usage, little-endian
+    fn report(&self) -> [u8; REPORT_SIZE] {
+        let usage = self.usage.get();
+        [(usage & 0xff) as u8, (usage >> 8) as u8]
+    }
+
+    fn set_usage(&'a self, usage: u16) {
+        self.usage.set(usage);
+        self.hid.report_changed();
+
+        // Alert the controller that we now have a report to send
+        self.controller().endpoint_resume_in(ENDPOINT_NUM);
+    }
+
+    /// Register a logger to receive our diagnostics instead of `debug!`
+    pub fn set_logger(&self, logger: &'a dyn Logger) {
+        self.client_ctrl.set_logger(logger);
+        self.hid.set_logger(logger);
+    }
+
+    #[inline]
+    fn controller(&'a self) -> &'a C {
+        self.client_ctrl.controller()
+    }
+}
+
+impl<'a, C: hil::usb::UsbController<'a>> hil::usb::Client<'a> for ConsumerControl<'a, C> {
+    fn enable(&'a self) {
+        // Set up the default control endpoint
+        self.client_ctrl.enable();
+
+        // Set up the interrupt IN endpoint for reports
+        self.controller()
+            .endpoint_set_buffer(ENDPOINT_NUM, &self.buffer.buf);
+        self.controller()
+            .endpoint_in_enable(TransferType::Interrupt, ENDPOINT_NUM);
+    }
+
+    fn attach(&'a self) -> ReturnCode {
+        self.client_ctrl.attach()
+    }
+
+    fn bus_reset(&'a self) {
+        self.client_ctrl.bus_reset();
+        self.hid.bus_reset();
+    }
+
+    /// Handle a Control Setup transaction
+    fn ctrl_setup(&'a self, endpoint: usize) -> hil::usb::CtrlSetupResult {
+        self.hid
+            .ctrl_setup(&self.client_ctrl, endpoint, &self.report())
+    }
+
+    /// Handle a Control In transaction
+    fn ctrl_in(&'a self, endpoint: usize) -> hil::usb::CtrlInResult {
+        self.client_ctrl.ctrl_in(endpoint)
+    }
+
+    /// Handle a Control Out transaction
+    fn ctrl_out(&'a self, endpoint: usize, packet_bytes: u32) -> hil::usb::CtrlOutResult {
+        self.client_ctrl.ctrl_out(endpoint, packet_bytes)
+    }
+
+    fn ctrl_status(&'a self, endpoint: usize) {
+        self.client_ctrl.ctrl_status(endpoint)
+    }
+
+    /// Handle the completion of a Control transfer
+    fn ctrl_status_complete(&'a self, endpoint: usize) {
+        self.client_ctrl.ctrl_status_complete(endpoint)
+    }
+
+    /// Handle a Bulk/Interrupt IN transaction
+    fn packet_in(&'a self, transfer_type: TransferType, endpoint: usize) -> hil::usb::InResult {
+        self.hid
+            .packet_in(transfer_type, endpoint, &self.buffer.buf, &self.report())
+    }
+
+    /// Handle a Bulk/Interrupt OUT transaction
+    fn packet_out(
+        &'a self,
+        _transfer_type: TransferType,
+        endpoint: usize,
+        _packet_bytes: u32,
+    ) -> hil::usb::OutResult {
+        self.hid.packet_out(endpoint)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::super::mock::MockController;
+    use super::{ConsumerControl, ENDPOINT_NUM};
+    use kernel::hil::usb::{Client, CtrlInResult, CtrlSetupResult, InResult, TransferType};
+
+    fn report(consumer: &'a ConsumerControl<'a, MockController<'a>>) -> Option<[u8; 2]> {
+        match consumer.packet_in(TransferType::Interrupt, ENDPOINT_NUM) {
+            InResult::Packet(2) => {
+                Some([consumer.buffer.buf[0].get(), consumer.buffer.buf[1].get()])
+            }
+            _ => None,
+        }
+    }
+
+    #[test]
+    pub fn consumer_reports() {
+        let controller = MockController::new();
+        let consumer = ConsumerControl::new(&controller);
+
+        // Nothing to report until a key is pressed
+        assert_eq!(report(&consumer), None);
+
+        consumer.press_consumer(0xE9); // Volume Increment
+        assert_eq!(controller.in_resumed.get(), 1);
+        assert_eq!(report(&consumer), Some([0xE9, 0x00]));
+        assert_eq!(report(&consumer), None);
+
+        consumer.press_consumer(0xCD); // Play/Pause
+        assert_eq!(report(&consumer), Some([0xCD, 0x00]));
+
+        consumer.release_consumer();
+        assert_eq!(report(&consumer), Some([0x00, 0x00]));
+    }
+
+    #[test]
+    pub fn get_report_returns_current_state() {
+        let controller = MockController::new();
+        let consumer = ConsumerControl::new(&controller);
+        consumer.enable();
+
+        consumer.press_consumer(0xCD); // Play/Pause
+        assert_eq!(report(&consumer), Some([0xCD, 0x00]));
+        assert_eq!(report(&consumer), None);
+
+        // GET_REPORT(Input, ID 0) on interface 0, with nothing new to report
+        controller.setup([0xa1, 0x01, 0x00, 0x01, 0x00, 0x00, 0x02, 0x00]);
+        assert!(match consumer.ctrl_setup(0) {
+            CtrlSetupResult::Ok => true,
+            _ => false,
+        });
+        assert!(match consumer.ctrl_in(0) {
+            CtrlInResult::Packet(2, true) => true,
+            _ => false,
+        });
+        let buf = controller.buffer(0);
+        assert_eq!([buf[0].get(), buf[1].get()], [0xCD, 0x00]);
+    }
+
+    #[test]
+    pub fn get_report_honors_requested_length() {
+        let controller = MockController::new();
+        let consumer = ConsumerControl::new(&controller);
+        consumer.enable();
+
+        // A usage above 0xFF, so that truncation drops a non-zero byte
+        consumer.press_consumer(0x1CD);
+
+        // GET_REPORT(Input, ID 0) on interface 0, asking for a single byte
+        controller.setup([0xa1, 0x01, 0x00, 0x01, 0x00, 0x00, 0x01, 0x00]);
+        assert!(match consumer.ctrl_setup(0) {
+            CtrlSetupResult::Ok => true,
+            _ => false,
+        });
+        assert!(match consumer.ctrl_in(0) {
+            CtrlInResult::Packet(1, true) => true,
+            _ => false,
+        });
+        assert_eq!(controller.buffer(0)[0].get(), 0xCD);
+    }
+
+    #[test]
+    pub fn unhandled_class_request_stalls() {
+        let controller = MockController::new();
+        let consumer = ConsumerControl::new(&controller);
+        consumer.enable();
+
+        // GET_PROTOCOL on interface 0, which only boot devices support
+        controller.setup([0xa1, 0x03, 0x00, 0x00, 0x00, 0x00, 0x01, 0x00]);
+        assert!(match consumer.ctrl_setup(0) {
+            CtrlSetupResult::ErrUnrecognizedRequestType => true,
+            _ => false,
+        });
+
+        // GET_IDLE for a report ID we do not have
+        controller.setup([0xa1, 0x02, 0x01, 0x00, 0x00, 0x00, 0x01, 0x00]);
+        assert!(match consumer.ctrl_setup(0) {
+            CtrlSetupResult::ErrUnrecognizedRequestType => true,
+            _ => false,
+        });
+    }
+}
@@ -0,0 +1,323 @@
+//! A USB HID gamepad, for demos
+//!
+//! It declares a single interrupt IN endpoint, on which it reports the state
+//! of eight buttons and of the X and Y axes whenever one of them changes.
+//!
+//! Usage
+//! -----
+//!
+//! ```rust
+//! let gamepad = static_init!(
+//!     capsules::usb::usbc_hid_gamepad::Gamepad<'static, sam4l::usbc::Usbc<'static>>,
+//!     capsules::usb::usbc_hid_gamepad::Gamepad::new(&sam4l::usbc::USBC));
+//! sam4l::usbc::USBC.set_client(gamepad);
+//!
+//! gamepad.enable();
+//! gamepad.attach();
+//! gamepad.set_axis(capsules::usb::usbc_hid_gamepad::Axis::X, -127); // Full left
+//! gamepad.set_button(0, true);
+//! ```
+
+use super::descriptors::Buffer8;
+use super::descriptors::DescriptorType;
+use super::descriptors::DeviceDescriptor;
+use super::descriptors::EndpointAddress;
+use super::descriptors::EndpointDescriptor;
+use super::descriptors::HIDCountryCode;
+use super::descriptors::HIDDescriptor;
+use super::descriptors::HIDSubordinateDescriptor;
+use super::descriptors::InterfaceDescriptor;
+use super::descriptors::ReportDescriptor;
+use super::descriptors::TransferDirection;
+use super::usbc_client_ctrl::ClientCtrl;
+use super::usbc_client_ctrl::ClientCtrlBuilder;
+use super::usbc_client_ctrl::Logger;
+use super::usbc_hid::HidReports;
+use core::cell::Cell;
+use kernel::hil;
+use kernel::hil::usb::TransferType;
+use kernel::ReturnCode;
+
+const VENDOR_ID: u16 = 0x6667;
+const PRODUCT_ID: u16 = 0xabd0;
+
+static STRINGS: &[&str] = &[
+    "XYZ Corp.",    // Manufacturer
+    "Gamepad",      // Product
+    "Serial No. 5", // Serial number
+];
+
+/// The interrupt IN endpoint carrying input reports
+const ENDPOINT_NUM: usize = 1;
+
+/// Number of buttons, one bit each in the first byte of the report
+pub const NUM_BUTTONS: usize = 8;
+
+/// Size of an input report: the buttons, then one signed byte per axis
+const REPORT_SIZE: usize = 3;
+
+static ENDPOINTS: &[EndpointDescriptor] = &[EndpointDescriptor {
+    endpoint_address: EndpointAddress::new_const(ENDPOINT_NUM, TransferDirection::DeviceToHost),
+    transfer_type: TransferType::Interrupt,
+    max_packet_size: 8,
+    interval: 10,
+}];
+
+const REPORT_DESCRIPTOR: &[u8] = &hid_report! {
+    usage_page(0x01), // Generic Desktop
+    usage(0x05),      // Game Pad
+    collection(application) {
+        usage_page(0x09), // Button
+        usage_minimum(0x01),
+        usage_maximum(0x08),
+        logical_minimum(0x00),
+        logical_maximum(0x01),
+        report_size(1),
+        report_count(8),
+        input(0x02), // Data, Variable, Absolute
+        usage_page(0x01), // Generic Desktop
+        usage(0x30),      // X
+        usage(0x31),      // Y
+        logical_minimum(-127),
+        logical_maximum(127),
+        report_size(8),
+        report_count(2),
+        input(0x02), // Data, Variable, Absolute
+    },
+};
+
+static REPORT: ReportDescriptor<'static> = ReportDescriptor {
+    desc: REPORT_DESCRIPTOR,
+};
+
+static SUB_HID_DESCRIPTORS: &[HIDSubordinateDescriptor] = &[HIDSubordinateDescriptor {
+    typ: DescriptorType::Report,
+    len: REPORT_DESCRIPTOR.len() as u16,
+}];
+
+static HID: HIDDescriptor<'static> = HIDDescriptor {
+    hid_class: 0x0111,
+    country_code: HIDCountryCode::NotSupported,
+    sub_descriptors: SUB_HID_DESCRIPTORS,
+};
+
+/// The axes reported by the gamepad
+#[derive(Copy, Clone, Debug)]
+pub enum Axis {
+    X = 0,
+    Y = 1,
+}
+
+pub struct Gamepad<'a, C: 'a> {
+    client_ctrl: ClientCtrl<'a, 'static, C>,
+
+    // Buffer for the interrupt IN endpoint
+    buffer: Buffer8,
+
+    // One bit per button, set while the button is pressed
+    buttons: Cell<u8>,
+
+    // The position of each axis
+    axes: [Cell<i8>; 2],
+
+    // When to send reports, and the HID class requests
+    hid: HidReports<'a>,
+}
+
+impl<'a, C: hil::usb::UsbController<'a>> Gamepad<'a, C> {
+    pub fn new(controller: &'a C) -> Self {
+        Gamepad {
+            client_ctrl: ClientCtrlBuilder::new(controller)
+                .device_descriptor(DeviceDescriptor {
+                    vendor_id: VENDOR_ID,
+                    product_id: PRODUCT_ID,
+                    manufacturer_string: 1,
+                    product_string: 2,
+                    serial_number_string: 3,
+                    ..Default::default()
+                })
+                .interface_descriptor(InterfaceDescriptor {
+                    interface_class: 0x03, // HID
+                    interface_subclass: 0x00,
+                    interface_protocol: 0x00,
+                    ..Default::default()
+                })
+                .endpoint_descriptors(ENDPOINTS)
+                .hid(&HID, &REPORT)
+                .strings(STRINGS)
+                .build(),
+            buffer: Default::default(),
+            buttons: Cell::new(0),
+            axes: Default::default(),
+            hid: HidReports::new("hid_gamepad", ENDPOINT_NUM),
+        }
+    }
+
+    /// Move `axis` to `value`, where -127 and 127 are the two ends of its range
+    pub fn set_axis(&'a self, axis: Axis, value: i8) {
+        // -128 is outside the logical range we declare
+        self.axes[axis as usize].set(value.max(-127));
+        self.state_changed();
+    }
+
+    /// Press or release the button with the given index, counting from zero
+    pub fn set_button(&'a self, index: usize, pressed: bool) -> ReturnCode {
+        if index >= NUM_BUTTONS {
+            return ReturnCode::EINVAL;
+        }
+        let mask = 1 << index;
+        let buttons = self.buttons.get();
+        self.buttons.set(if pressed {
+            buttons | mask
+        } else {
+            buttons & !mask
+        });
+        self.state_changed();
+        ReturnCode::SUCCESS
+    }
+
+    /// The idle rate the host set for the report with the given ID, in units of 4 ms
+    pub fn hid_idle_rate(&self, report_id: u8) -> Option<u8> {
+        self.hid.hid_idle_rate(report_id)
+    }
+
+    /// Skip sending a report when it is identical to the last one sent
+    pub fn set_coalesce_reports(&self, coalesce: bool) {
+        self.hid.set_coalesce_reports(coalesce);
+    }
+
+    /// The input report for the current state
+    fn report(&self) -> [u8; REPORT_SIZE] {
+        [
+            self.buttons.get(),
+            self.axes[Axis::X as usize].get() as u8,
+            self.axes[Axis::Y as usize].get() as u8,
+        ]
+    }
+
+    fn state_changed(&'a self) {
+        self.hid.report_changed();
+
+        // Alert the controller that we now have a report to send
+        self.controller().endpoint_resume_in(ENDPOINT_NUM);
+    }
+
+    /// Register a logger to receive our diagnostics instead of `debug!`
+    pub fn set_logger(&self, logger: &'a dyn Logger) {
+        self.client_ctrl.set_logger(logger);
+        self.hid.set_logger(logger);
+    }
+
+    #[inline]
+    fn controller(&'a self) -> &'a C {
+        self.client_ctrl.controller()
+    }
+}
+
+impl<'a, C: hil::usb::UsbController<'a>> hil::usb::Client<'a> for Gamepad<'a, C> {
+    fn enable(&'a self) {
+        // Set up the default control endpoint
+        self.client_ctrl.enable();
+
+        // Set up the interrupt IN endpoint for reports
+        self.controller()
+            .endpoint_set_buffer(ENDPOINT_NUM, &self.buffer.buf);
+        self.controller()
+            .endpoint_in_enable(TransferType::Interrupt, ENDPOINT_NUM);
+    }
+
+    fn attach(&'a self) -> ReturnCode {
+        self.client_ctrl.attach()
+    }
+
+    fn bus_reset(&'a self) {
+        self.client_ctrl.bus_reset();
+        self.hid.bus_reset();
+    }
+
+    /// Handle a Control Setup transaction
+    fn ctrl_setup(&'a self, endpoint: usize) -> hil::usb::CtrlSetupResult {
+        self.hid
+            .ctrl_setup(&self.client_ctrl, endpoint, &self.report())
+    }
+
+    /// Handle a Control In transaction
+    fn ctrl_in(&'a self, endpoint: usize) -> hil::usb::CtrlInResult {
+        self.client_ctrl.ctrl_in(endpoint)
+    }
+
+    /// Handle a Control Out transaction
+    fn ctrl_out(&'a self, endpoint: usize, packet_bytes: u32) -> hil::usb::CtrlOutResult {
+        self.client_ctrl.ctrl_out(endpoint, packet_bytes)
+    }
+
+    fn ctrl_status(&'a self, endpoint: usize) {
+        self.client_ctrl.ctrl_status(endpoint)
+    }
+
+    /// Handle the completion of a Control transfer
+    fn ctrl_status_complete(&'a self, endpoint: usize) {
+        self.client_ctrl.ctrl_status_complete(endpoint)
+    }
+
+    /// Handle a Bulk/Interrupt IN transaction
+    fn packet_in(&'a self, transfer_type: TransferType, endpoint: usize) -> hil::usb::InResult {
+        self.hid
+            .packet_in(transfer_type, endpoint, &self.buffer.buf, &self.report())
+    }
+
+    /// Handle a Bulk/Interrupt OUT transaction
+    fn packet_out(
+        &'a self,
+        _transfer_type: TransferType,
+        endpoint: usize,
+        _packet_bytes: u32,
+    ) -> hil::usb::OutResult {
+        self.hid.packet_out(endpoint)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::super::mock::MockController;
+    use super::{Axis, Gamepad, ENDPOINT_NUM, REPORT_SIZE};
+    use kernel::hil::usb::{Client, InResult, TransferType};
+    use kernel::ReturnCode;
+
+    fn report(gamepad: &'a Gamepad<'a, MockController<'a>>) -> Option<[u8; REPORT_SIZE]> {
+        match gamepad.packet_in(TransferType::Interrupt, ENDPOINT_NUM) {
+            InResult::Packet(REPORT_SIZE) => Some([
+                gamepad.buffer.buf[0].get(),
+                gamepad.buffer.buf[1].get(),
+                gamepad.buffer.buf[2].get(),
+            ]),
+            _ => None,
+        }
+    }
+
+    #[test]
+    pub fn gamepad_reports() {
+        let controller = MockController::new();
+        let gamepad = Gamepad::new(&controller);
+
+        // Nothing to report until something changes
+        assert_eq!(report(&gamepad), None);
+
+        gamepad.set_axis(Axis::X, -127);
+        assert_eq!(controller.in_resumed.get(), 1);
+        assert_eq!(report(&gamepad), Some([0x00, 0x81, 0x00]));
+        assert_eq!(report(&gamepad), None);
+
+        gamepad.set_axis(Axis::Y, 100);
+        assert_eq!(gamepad.set_button(0, true), ReturnCode::SUCCESS);
+        assert_eq!(gamepad.set_button(7, true), ReturnCode::SUCCESS);
+        assert_eq!(report(&gamepad), Some([0x81, 0x81, 100]));
+
+        assert_eq!(gamepad.set_button(0, false), ReturnCode::SUCCESS);
+        gamepad.set_axis(Axis::X, -128); // Clamped to the declared range
+        assert_eq!(report(&gamepad), Some([0x80, 0x81, 100]));
+
+        assert_eq!(gamepad.set_button(8, true), ReturnCode::EINVAL);
+        assert_eq!(report(&gamepad), None);
+    }
+}
@@ -0,0 +1,919 @@
+//! A read-only USB mass storage device
+//!
+//! Presents a volume image in memory to the host as a single logical unit,
+//! using the bulk-only transport and the SCSI transparent command set.  Each
+//! command arrives as a Command Block Wrapper (CBW) on the bulk OUT endpoint
+//! 2, is followed by an optional data phase, and is answered by a Command
+//! Status Wrapper (CSW) on the bulk IN endpoint 1.
+//!
+//! The volume is write-protected: WRITE(10) fails with the DATA PROTECT sense
+//! key, and any data the host sends with it is discarded.  Like the other
+//! capsules built on `ClientCtrl` this device has a single interface, so it
+//! cannot be combined with a HID interface into a composite device.
+//!
+//! The bulk-only transport answers an invalid CBW by halting both bulk
+//! endpoints until the host's Reset Recovery clears them. Neither the
+//! controllers nor `ClientCtrl` can clear an endpoint halt, so a halted
+//! endpoint would stay halted; instead this capsule never stalls its bulk
+//! endpoints, and ignores packets it cannot make sense of.
+//!
+//! This capsule only works on controllers that report each transmitted IN
+//! packet through `packet_transmitted`, such as the nRF52 one: that is what
+//! moves a data phase on to its next packet, and to the CSW.  The SAM4L
+//! controller never reports transmits, so on it a command with a data-in
+//! phase would never complete.  The bulk endpoints also use 64-byte packets,
+//! which the controller must support.
+//!
+//! Usage
+//! -----
+//!
+//! ```rust
+//! static VOLUME: [u8; 8 * 512] = [...];
+//!
+//! let msc = static_init!(
+//!     capsules::usb::usbc_msc::MassStorage<'static, nrf52::usbd::Usbd<'static>>,
+//!     capsules::usb::usbc_msc::MassStorage::new(&nrf52::usbd::USBD, &VOLUME));
+//! nrf52::usbd::USBD.set_client(msc);
+//!
+//! msc.enable();
+//! msc.attach();
+//! ```
+
+use super::descriptors::Buffer64;
+use super::descriptors::DeviceDescriptor;
+use super::descriptors::EndpointAddress;
+use super::descriptors::EndpointDescriptor;
+use super::descriptors::InterfaceDescriptor;
+use super::descriptors::MSCRequest;
+use super::descriptors::TransferDirection;
+use super::usbc_client_ctrl::ClientCtrl;
+use super::usbc_client_ctrl::ClientCtrlBuilder;
+use super::usbc_client_ctrl::Logger;
+use core::cell::Cell;
+use kernel::hil;
+use kernel::hil::usb::TransferType;
+use kernel::ReturnCode;
+
+const VENDOR_ID: u16 = 0x6667;
+const PRODUCT_ID: u16 = 0xabd1;
+
+static STRINGS: &[&str] = &[
+    "XYZ Corp.",    // Manufacturer
+    "Docs Volume",  // Product
+    "Serial No. 6", // Serial number
+];
+
+const IN_ENDPOINT: usize = 1;
+const OUT_ENDPOINT: usize = 2;
+
+/// Max packet size of both bulk endpoints
+const MAX_PACKET_SIZE: usize = 64;
+
+static ENDPOINTS: &[EndpointDescriptor] = &[
+    EndpointDescriptor {
+        endpoint_address: EndpointAddress::new_const(IN_ENDPOINT, TransferDirection::DeviceToHost),
+        transfer_type: TransferType::Bulk,
+        max_packet_size: MAX_PACKET_SIZE as u16,
+        interval: 0,
+    },
+    EndpointDescriptor {
+        endpoint_address: EndpointAddress::new_const(OUT_ENDPOINT, TransferDirection::HostToDevice),
+        transfer_type: TransferType::Bulk,
+        max_packet_size: MAX_PACKET_SIZE as u16,
+        interval: 0,
+    },
+];
+
+/// Size of a logical block of the volume
+pub const BLOCK_SIZE: usize = 512;
+
+const CBW_SIGNATURE: [u8; 4] = *b"USBC";
+const CBW_LENGTH: usize = 31;
+const CSW_SIGNATURE: [u8; 4] = *b"USBS";
+const CSW_LENGTH: usize = 13;
+
+// SCSI operation codes
+const TEST_UNIT_READY: u8 = 0x00;
+const REQUEST_SENSE: u8 = 0x03;
+const INQUIRY: u8 = 0x12;
+const MODE_SENSE_6: u8 = 0x1A;
+const PREVENT_ALLOW_MEDIUM_REMOVAL: u8 = 0x1E;
+const READ_CAPACITY_10: u8 = 0x25;
+const READ_10: u8 = 0x28;
+const WRITE_10: u8 = 0x2A;
+
+/// Standard INQUIRY data: a removable direct-access device
+static INQUIRY_DATA: [u8; 36] = [
+    0x00, // Direct-access block device
+    0x80, // Removable
+    0x04, // SPC-2
+    0x02, // Response data format
+    31,   // Additional length
+    0x00, 0x00, 0x00, //
+    b'X', b'Y', b'Z', b' ', b'C', b'o', b'r', b'p', // Vendor
+    b'D', b'o', b'c', b's', b' ', b'V', b'o', b'l', // Product
+    b'u', b'm', b'e', b' ', b' ', b' ', b' ', b' ', //
+    b'0', b'0', b'0', b'1', // Revision
+];
+
+/// MODE SENSE(6) header with no block descriptors or pages
+static MODE_SENSE_DATA: [u8; 4] = [
+    3,    // Mode data length
+    0x00, // Medium type
+    0x80, // Write protected
+    0x00, // Block descriptor length
+];
+
+/// Sense data describing why the last command failed, as (sense key,
+/// additional sense code, additional sense code qualifier)
+#[derive(Copy, Clone, Debug, PartialEq)]
+struct Sense(u8, u8, u8);
+
+const NO_SENSE: Sense = Sense(0x00, 0x00, 0x00);
+const INVALID_COMMAND: Sense = Sense(0x05, 0x20, 0x00);
+const LBA_OUT_OF_RANGE: Sense = Sense(0x05, 0x21, 0x00);
+const INVALID_FIELD_IN_CDB: Sense = Sense(0x05, 0x24, 0x00);
+const WRITE_PROTECTED: Sense = Sense(0x07, 0x27, 0x00);
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+enum Status {
+    Passed = 0,
+    Failed = 1,
+    PhaseError = 2,
+}
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+enum State {
+    /// Waiting for a CBW
+    Command,
+    /// Sending data to the host
+    DataIn,
+    /// Receiving (and discarding) data from the host
+    DataOut,
+    /// Waiting to send the CSW
+    Status,
+}
+
+/// Where the data of the current IN phase comes from
+#[derive(Copy, Clone, Debug)]
+enum Source {
+    /// A constant response
+    Static(&'static [u8]),
+    /// `scratch`, filled in when the command was executed
+    Scratch,
+    /// The volume image
+    Volume,
+}
+
+pub struct MassStorage<'a, C: 'a> {
+    client_ctrl: ClientCtrl<'a, 'static, C>,
+
+    // Buffers for the bulk IN and OUT endpoints
+    in_buffer: Buffer64,
+    out_buffer: Buffer64,
+
+    // The volume image, a whole number of blocks
+    volume: &'a [u8],
+
+    // Responses that depend on the volume or on the device state
+    scratch: [Cell<u8>; 18],
+
+    state: Cell<State>,
+
+    // The bytes of `source` still to be sent in the IN phase
+    source: Cell<Source>,
+    offset: Cell<usize>,
+    end: Cell<usize>,
+
+    // Bytes still expected from the host in the OUT phase
+    out_remaining: Cell<u32>,
+
+    // Fields of the CSW for the current command
+    tag: Cell<u32>,
+    residue: Cell<u32>,
+    status: Cell<Status>,
+
+    // Reported by REQUEST SENSE for the last failed command
+    sense: Cell<Sense>,
+
+    // Whether we NAKed a CBW because the previous CSW had not been sent yet
+    delayed_out: Cell<bool>,
+}
+
+impl<'a, C: hil::usb::UsbController<'a>> MassStorage<'a, C> {
+    pub fn new(controller: &'a C, volume: &'a [u8]) -> Self {
+        if volume.is_empty() || volume.len() % BLOCK_SIZE != 0 {
+            panic!(
+                "Volume of {} bytes is not a whole number of {}-byte blocks",
+                volume.len(),
+                BLOCK_SIZE
+            );
+        }
+
+        MassStorage {
+            client_ctrl: ClientCtrlBuilder::new(controller)
+                .device_descriptor(DeviceDescriptor {
+                    vendor_id: VENDOR_ID,
+                    product_id: PRODUCT_ID,
+                    manufacturer_string: 1,
+                    product_string: 2,
+                    serial_number_string: 3,
+                    max_packet_size_ep0: 64,
+                    ..Default::default()
+                })
+                .interface_descriptor(InterfaceDescriptor {
+                    interface_class: 0x08,    // Mass storage
+                    interface_subclass: 0x06, // SCSI transparent command set
+                    interface_protocol: 0x50, // Bulk-only transport
+                    ..Default::default()
+                })
+                .endpoint_descriptors(ENDPOINTS)
+                .strings(STRINGS)
+                .build(),
+            in_buffer: Default::default(),
+            out_buffer: Default::default(),
+            volume,
+            scratch: Default::default(),
+            state: Cell::new(State::Command),
+            source: Cell::new(Source::Static(&[])),
+            offset: Cell::new(0),
+            end: Cell::new(0),
+            out_remaining: Cell::new(0),
+            tag: Cell::new(0),
+            residue: Cell::new(0),
+            status: Cell::new(Status::Passed),
+            sense: Cell::new(NO_SENSE),
+            delayed_out: Cell::new(false),
+        }
+    }
+
+    /// Register a logger to receive our diagnostics instead of `debug!`
+    pub fn set_logger(&self, logger: &'a dyn Logger) {
+        self.client_ctrl.set_logger(logger);
+    }
+
+    #[inline]
+    fn controller(&'a self) -> &'a C {
+        self.client_ctrl.controller()
+    }
+
+    fn blocks(&self) -> usize {
+        self.volume.len() / BLOCK_SIZE
+    }
+
+    /// Return to waiting for a CBW, abandoning any command in progress
+    fn reset(&self) {
+        self.state.set(State::Command);
+        self.set_source(Source::Static(&[]), 0, 0);
+        self.out_remaining.set(0);
+        self.residue.set(0);
+        self.status.set(Status::Passed);
+        self.sense.set(NO_SENSE);
+    }
+
+    fn set_source(&self, source: Source, offset: usize, end: usize) {
+        self.source.set(source);
+        self.offset.set(offset);
+        self.end.set(end);
+    }
+
+    fn set_scratch(&self, data: &[u8]) {
+        for (b, v) in self.scratch.iter().zip(data.iter()) {
+            b.set(*v);
+        }
+        self.set_source(Source::Scratch, 0, data.len());
+    }
+
+    /// Execute a SCSI command block, setting the command status and the data
+    /// to return to the host
+    fn execute(&self, cb: &[u8; 16]) {
+        self.set_source(Source::Static(&[]), 0, 0);
+        let result = match cb[0] {
+            TEST_UNIT_READY | PREVENT_ALLOW_MEDIUM_REMOVAL => Ok(()),
+            REQUEST_SENSE => {
+                // Fixed format sense data
+                let Sense(key, asc, ascq) = self.sense.get();
+                let allocation_length = cb[4] as usize;
+                let data = [
+                    0x70, 0, key, 0, 0, 0, 0, 10, 0, 0, 0, 0, asc, ascq, 0, 0, 0, 0,
+                ];
+                self.set_scratch(&data[..allocation_length.min(data.len())]);
+                Ok(())
+            }
+            INQUIRY => {
+                if cb[1] & 0x01 != 0 {
+                    // We have no vital product data pages
+                    Err(INVALID_FIELD_IN_CDB)
+                } else {
+                    let allocation_length = u16::from_be_bytes([cb[3], cb[4]]) as usize;
+                    let length = allocation_length.min(INQUIRY_DATA.len());
+                    self.set_source(Source::Static(&INQUIRY_DATA), 0, length);
+                    Ok(())
+                }
+            }
+            MODE_SENSE_6 => {
+                let allocation_length = cb[4] as usize;
+                let length = allocation_length.min(MODE_SENSE_DATA.len());
+                self.set_source(Source::Static(&MODE_SENSE_DATA), 0, length);
+                Ok(())
+            }
+            READ_CAPACITY_10 => {
+                let last_lba = (self.blocks() - 1) as u32;
+                let mut data = [0; 8];
+                data[0..4].copy_from_slice(&last_lba.to_be_bytes());
+                data[4..8].copy_from_slice(&(BLOCK_SIZE as u32).to_be_bytes());
+                self.set_scratch(&data);
+                Ok(())
+            }
+            READ_10 => {
+                // The host picks the LBA, so check the range in 64 bits before any of it is
+                // used as an offset
+                let lba = u32::from_be_bytes([cb[2], cb[3], cb[4], cb[5]]) as u64;
+                let count = u16::from_be_bytes([cb[7], cb[8]]) as u64;
+                if lba + count > self.blocks() as u64 {
+                    Err(LBA_OUT_OF_RANGE)
+                } else {
+                    let (lba, count) = (lba as usize, count as usize);
+                    self.set_source(Source::Volume, lba * BLOCK_SIZE, (lba + count) * BLOCK_SIZE);
+                    Ok(())
+                }
+            }
+            WRITE_10 => Err(WRITE_PROTECTED),
+            _ => Err(INVALID_COMMAND),
+        };
+
+        match result {
+            Ok(()) => {
+                self.status.set(Status::Passed);
+                self.sense.set(NO_SENSE);
+            }
+            Err(sense) => {
+                self.status.set(Status::Failed);
+                self.sense.set(sense);
+            }
+        }
+    }
+
+    /// Handle a CBW received on the OUT endpoint
+    fn command(&'a self, packet_bytes: usize) -> hil::usb::OutResult {
+        let mut cbw = [0; CBW_LENGTH];
+        for (b, v) in cbw.iter_mut().zip(self.out_buffer.buf.iter()) {
+            *b = v.get();
+        }
+        let cb_length = cbw[14] as usize;
+        if packet_bytes != CBW_LENGTH
+            || cbw[0..4] != CBW_SIGNATURE
+            || cb_length == 0
+            || cb_length > 16
+        {
+            // Wait for a valid one
+            self.client_ctrl
+                .log(format_args!("msc: invalid CBW of {} bytes", packet_bytes));
+            return hil::usb::OutResult::Ok;
+        }
+
+        let tag = u32::from_le_bytes([cbw[4], cbw[5], cbw[6], cbw[7]]);
+        let length = u32::from_le_bytes([cbw[8], cbw[9], cbw[10], cbw[11]]);
+        let device_to_host = cbw[12] & 0x80 != 0;
+        let mut cb = [0; 16];
+        cb[..cb_length].copy_from_slice(&cbw[15..15 + cb_length]);
+
+        self.tag.set(tag);
+        self.residue.set(length);
+        self.execute(&cb);
+
+        let has_data = self.end.get() > self.offset.get();
+        if has_data && (length == 0 || !device_to_host) {
+            // The host does not expect the data this command returns
+            self.set_source(Source::Static(&[]), 0, 0);
+            self.status.set(Status::PhaseError);
+        }
+
+        if length == 0 {
+            self.state.set(State::Status);
+        } else if device_to_host {
+            self.state.set(State::DataIn);
+        } else {
+            self.out_remaining.set(length);
+            self.state.set(State::DataOut);
+            return hil::usb::OutResult::Ok;
+        }
+
+        // Alert the controller that we now have data to send
+        self.controller().endpoint_resume_in(IN_ENDPOINT);
+        hil::usb::OutResult::Ok
+    }
+
+    /// Fill the IN buffer with the next packet of the data phase
+    fn data_in(&self) -> usize {
+        let offset = self.offset.get();
+        let available = self.end.get() - offset;
+        let packet_bytes = MAX_PACKET_SIZE
+            .min(available)
+            .min(self.residue.get() as usize);
+
+        let data = &self.in_buffer.buf[..packet_bytes];
+        match self.source.get() {
+            Source::Static(response) => {
+                for (b, v) in data.iter().zip(response[offset..].iter()) {
+                    b.set(*v);
+                }
+            }
+            Source::Scratch => {
+                for (b, v) in data.iter().zip(self.scratch[offset..].iter()) {
+                    b.set(v.get());
+                }
+            }
+            Source::Volume => {
+                for (b, v) in data.iter().zip(self.volume[offset..].iter()) {
+                    b.set(*v);
+                }
+            }
+        }
+        self.offset.set(offset + packet_bytes);
+        self.residue.set(self.residue.get() - packet_bytes as u32);
+
+        // A short packet, possibly of zero length, ends the data phase early
+        if packet_bytes < MAX_PACKET_SIZE || self.residue.get() == 0 {
+            self.state.set(State::Status);
+        }
+        packet_bytes
+    }
+
+    /// Fill the IN buffer with the CSW for the current command
+    fn status(&self) -> usize {
+        let mut csw = [0; CSW_LENGTH];
+        csw[0..4].copy_from_slice(&CSW_SIGNATURE);
+        csw[4..8].copy_from_slice(&self.tag.get().to_le_bytes());
+        csw[8..12].copy_from_slice(&self.residue.get().to_le_bytes());
+        csw[12] = self.status.get() as u8;
+        for (b, v) in self.in_buffer.buf.iter().zip(csw.iter()) {
+            b.set(*v);
+        }
+        CSW_LENGTH
+    }
+}
+
+impl<'a, C: hil::usb::UsbController<'a>> hil::usb::Client<'a> for MassStorage<'a, C> {
+    fn enable(&'a self) {
+        // Set up the default control endpoint
+        self.client_ctrl.enable();
+
+        self.controller()
+            .endpoint_set_buffer(IN_ENDPOINT, &self.in_buffer.buf);
+        self.controller()
+            .endpoint_in_enable(TransferType::Bulk, IN_ENDPOINT);
+
+        self.controller()
+            .endpoint_set_buffer(OUT_ENDPOINT, &self.out_buffer.buf);
+        self.controller()
+            .endpoint_out_enable(TransferType::Bulk, OUT_ENDPOINT);
+    }
+
+    fn attach(&'a self) -> ReturnCode {
+        self.client_ctrl.attach()
+    }
+
+    fn bus_reset(&'a self) {
+        self.client_ctrl.bus_reset();
+
+        self.reset();
+        self.delayed_out.set(false);
+    }
+
+    /// Handle a Control Setup transaction
+    fn ctrl_setup(&'a self, endpoint: usize) -> hil::usb::CtrlSetupResult {
+        let interface = self.client_ctrl.interface_number() as u16;
+        self.client_ctrl.ctrl_setup_with(endpoint, |setup_data| {
+            match setup_data.get_msc_class_request() {
+                Some(MSCRequest::GetMaxLun { interface: i }) if i == interface => {
+                    // We have a single logical unit
+                    Some(self.client_ctrl.ctrl_in_reply(endpoint, &[0], 1))
+                }
+                Some(MSCRequest::BulkOnlyReset { interface: i }) if i == interface => {
+                    self.reset();
+                    if self.delayed_out.take() {
+                        self.controller().endpoint_resume_out(OUT_ENDPOINT);
+                    }
+                    Some(hil::usb::CtrlSetupResult::Ok)
+                }
+                _ => None,
+            }
+        })
+    }
+
+    /// Handle a Control In transaction
+    fn ctrl_in(&'a self, endpoint: usize) -> hil::usb::CtrlInResult {
+        self.client_ctrl.ctrl_in(endpoint)
+    }
+
+    /// Handle a Control Out transaction
+    fn ctrl_out(&'a self, endpoint: usize, packet_bytes: u32) -> hil::usb::CtrlOutResult {
+        self.client_ctrl.ctrl_out(endpoint, packet_bytes)
+    }
+
+    fn ctrl_status(&'a self, endpoint: usize) {
+        self.client_ctrl.ctrl_status(endpoint)
+    }
+
+    /// Handle the completion of a Control transfer
+    fn ctrl_status_complete(&'a self, endpoint: usize) {
+        self.client_ctrl.ctrl_status_complete(endpoint)
+    }
+
+    /// Handle a Bulk/Interrupt IN transaction
+    fn packet_in(&'a self, transfer_type: TransferType, endpoint: usize) -> hil::usb::InResult {
+        match (transfer_type, endpoint) {
+            (TransferType::Bulk, IN_ENDPOINT) => match self.state.get() {
+                State::DataIn => hil::usb::InResult::Packet(self.data_in()),
+                State::Status => {
+                    let packet_bytes = self.status();
+                    self.state.set(State::Command);
+
+                    // We are ready for the next command
+                    if self.delayed_out.take() {
+                        self.controller().endpoint_resume_out(OUT_ENDPOINT);
+                    }
+                    hil::usb::InResult::Packet(packet_bytes)
+                }
+                State::Command | State::DataOut => hil::usb::InResult::Delay,
+            },
+            (TransferType::Bulk, _) => {
+                self.client_ctrl.log(format_args!(
+                    "msc: packet_in on unknown endpoint {}",
+                    endpoint
+                ));
+                hil::usb::InResult::Delay
+            }
+            (_, _) => {
+                self.client_ctrl.log(format_args!(
+                    "msc: packet_in({}) is not a bulk transfer",
+                    endpoint
+                ));
+                hil::usb::InResult::Delay
+            }
+        }
+    }
+
+    /// Handle a Bulk/Interrupt OUT transaction
+    fn packet_out(
+        &'a self,
+        transfer_type: TransferType,
+        endpoint: usize,
+        packet_bytes: u32,
+    ) -> hil::usb::OutResult {
+        match (transfer_type, endpoint) {
+            (TransferType::Bulk, OUT_ENDPOINT) => match self.state.get() {
+                State::Command => self.command(packet_bytes as usize),
+                State::DataOut => {
+                    // We are read-only, so the data is discarded and counted
+                    // in the residue
+                    let remaining = self.out_remaining.get().saturating_sub(packet_bytes);
+                    self.out_remaining.set(remaining);
+                    if remaining == 0 || (packet_bytes as usize) < MAX_PACKET_SIZE {
+                        self.state.set(State::Status);
+                        self.controller().endpoint_resume_in(IN_ENDPOINT);
+                    }
+                    hil::usb::OutResult::Ok
+                }
+                State::DataIn | State::Status => {
+                    // The CSW for the previous command has not been sent yet
+                    self.delayed_out.set(true);
+                    hil::usb::OutResult::Delay
+                }
+            },
+            (TransferType::Bulk, _) => {
+                self.client_ctrl.log(format_args!(
+                    "msc: packet_out on unknown endpoint {}",
+                    endpoint
+                ));
+                hil::usb::OutResult::Ok
+            }
+            (_, _) => {
+                self.client_ctrl.log(format_args!(
+                    "msc: packet_out({}) is not a bulk transfer",
+                    endpoint
+                ));
+                hil::usb::OutResult::Ok
+            }
+        }
+    }
+
+    fn packet_transmitted(&'a self, endpoint: usize, _packet_bytes: usize) {
+        // Controllers that wait for a resume after each IN packet need one to
+        // continue the data phase or send the CSW
+        if endpoint == IN_ENDPOINT {
+            match self.state.get() {
+                State::DataIn | State::Status => self.controller().endpoint_resume_in(IN_ENDPOINT),
+                State::Command | State::DataOut => {}
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::super::mock::{MockController, MockLogger};
+    use super::{MassStorage, BLOCK_SIZE, IN_ENDPOINT, OUT_ENDPOINT};
+    use kernel::hil::usb::{Client, InResult, OutResult, TransferType};
+
+    const PASSED: u8 = 0;
+    const FAILED: u8 = 1;
+
+    fn volume() -> [u8; 4 * BLOCK_SIZE] {
+        let mut volume = [0; 4 * BLOCK_SIZE];
+        for (i, b) in volume.iter_mut().enumerate() {
+            *b = (i / BLOCK_SIZE) as u8 ^ i as u8;
+        }
+        volume
+    }
+
+    fn command(
+        msc: &'a MassStorage<'a, MockController<'a>>,
+        controller: &MockController<'a>,
+        tag: u32,
+        length: u32,
+        device_to_host: bool,
+        cb: &[u8],
+    ) {
+        let mut cbw = [0; 31];
+        cbw[0..4].copy_from_slice(b"USBC");
+        cbw[4..8].copy_from_slice(&tag.to_le_bytes());
+        cbw[8..12].copy_from_slice(&length.to_le_bytes());
+        cbw[12] = if device_to_host { 0x80 } else { 0x00 };
+        cbw[14] = cb.len() as u8;
+        cbw[15..15 + cb.len()].copy_from_slice(cb);
+        for (b, v) in controller.buffer(OUT_ENDPOINT).iter().zip(cbw.iter()) {
+            b.set(*v);
+        }
+        assert!(match msc.packet_out(TransferType::Bulk, OUT_ENDPOINT, 31) {
+            OutResult::Ok => true,
+            _ => false,
+        });
+    }
+
+    /// Receive one packet of the data phase and check its contents
+    fn data_in(
+        msc: &'a MassStorage<'a, MockController<'a>>,
+        controller: &MockController<'a>,
+        expected: &[u8],
+    ) {
+        let packet_bytes = match msc.packet_in(TransferType::Bulk, IN_ENDPOINT) {
+            InResult::Packet(n) => n,
+            _ => panic!("no data packet"),
+        };
+        assert_eq!(packet_bytes, expected.len());
+        for (b, v) in controller.buffer(IN_ENDPOINT).iter().zip(expected.iter()) {
+            assert_eq!(b.get(), *v);
+        }
+    }
+
+    fn status(
+        msc: &'a MassStorage<'a, MockController<'a>>,
+        controller: &MockController<'a>,
+        tag: u32,
+        residue: u32,
+        status: u8,
+    ) {
+        let mut csw = [0; 13];
+        csw[0..4].copy_from_slice(b"USBS");
+        csw[4..8].copy_from_slice(&tag.to_le_bytes());
+        csw[8..12].copy_from_slice(&residue.to_le_bytes());
+        csw[12] = status;
+        data_in(msc, controller, &csw);
+
+        // The device is now waiting for the next command
+        assert!(match msc.packet_in(TransferType::Bulk, IN_ENDPOINT) {
+            InResult::Delay => true,
+            _ => false,
+        });
+    }
+
+    #[test]
+    pub fn inquiry() {
+        let volume = volume();
+        let controller = MockController::new();
+        let msc = MassStorage::new(&controller, &volume);
+        msc.enable();
+
+        command(&msc, &controller, 7, 36, true, &[0x12, 0, 0, 0, 36, 0]);
+        let mut expected = [0; 36];
+        expected[..5].copy_from_slice(&[0x00, 0x80, 0x04, 0x02, 31]);
+        expected[8..16].copy_from_slice(b"XYZ Corp");
+        expected[16..32].copy_from_slice(b"Docs Volume     ");
+        expected[32..36].copy_from_slice(b"0001");
+        data_in(&msc, &controller, &expected);
+        status(&msc, &controller, 7, 0, PASSED);
+
+        // A shorter allocation length truncates the response
+        command(&msc, &controller, 8, 36, true, &[0x12, 0, 0, 0, 5, 0]);
+        data_in(&msc, &controller, &expected[..5]);
+        status(&msc, &controller, 8, 31, PASSED);
+    }
+
+    #[test]
+    pub fn read_capacity_and_test_unit_ready() {
+        let volume = volume();
+        let controller = MockController::new();
+        let msc = MassStorage::new(&controller, &volume);
+        msc.enable();
+
+        command(&msc, &controller, 1, 0, false, &[0x00, 0, 0, 0, 0, 0]);
+        status(&msc, &controller, 1, 0, PASSED);
+
+        command(
+            &msc,
+            &controller,
+            2,
+            8,
+            true,
+            &[0x25, 0, 0, 0, 0, 0, 0, 0, 0, 0],
+        );
+        data_in(&msc, &controller, &[0, 0, 0, 3, 0, 0, 0x02, 0x00]);
+        status(&msc, &controller, 2, 0, PASSED);
+    }
+
+    #[test]
+    pub fn mode_sense_reports_write_protect() {
+        let volume = volume();
+        let controller = MockController::new();
+        let msc = MassStorage::new(&controller, &volume);
+        msc.enable();
+
+        command(&msc, &controller, 3, 192, true, &[0x1A, 0, 0x3F, 0, 192, 0]);
+        data_in(&msc, &controller, &[3, 0, 0x80, 0]);
+        status(&msc, &controller, 3, 188, PASSED);
+    }
+
+    #[test]
+    pub fn read_10() {
+        let volume = volume();
+        let controller = MockController::new();
+        let msc = MassStorage::new(&controller, &volume);
+        msc.enable();
+
+        // Read blocks 2 and 3
+        let length = 2 * BLOCK_SIZE;
+        command(
+            &msc,
+            &controller,
+            4,
+            length as u32,
+            true,
+            &[0x28, 0, 0, 0, 0, 2, 0, 0, 2, 0],
+        );
+
+        // The next command must wait for this one to finish
+        assert!(match msc.packet_out(TransferType::Bulk, OUT_ENDPOINT, 31) {
+            OutResult::Delay => true,
+            _ => false,
+        });
+
+        for offset in (0..length).step_by(64) {
+            let start = 2 * BLOCK_SIZE + offset;
+            data_in(&msc, &controller, &volume[start..start + 64]);
+
+            // Each transmitted packet lets the controller continue
+            let resumed_in = controller.in_resumed.get();
+            msc.packet_transmitted(IN_ENDPOINT, 64);
+            assert_eq!(controller.in_resumed.get(), resumed_in + 1);
+        }
+
+        let resumed_out = controller.out_resumed.get();
+        status(&msc, &controller, 4, 0, PASSED);
+        assert_eq!(controller.out_resumed.get(), resumed_out + 1);
+
+        // Reading past the end of the volume fails
+        command(
+            &msc,
+            &controller,
+            5,
+            1024,
+            true,
+            &[0x28, 0, 0, 0, 0, 3, 0, 0, 2, 0],
+        );
+        data_in(&msc, &controller, &[]);
+        status(&msc, &controller, 5, 1024, FAILED);
+
+        // An LBA near the top of the 32-bit range must not wrap around into the volume
+        command(
+            &msc,
+            &controller,
+            6,
+            1024,
+            true,
+            &[0x28, 0, 0xff, 0xff, 0xff, 0xff, 0, 0, 2, 0],
+        );
+        data_in(&msc, &controller, &[]);
+        status(&msc, &controller, 6, 1024, FAILED);
+    }
+
+    #[test]
+    pub fn write_10_is_rejected() {
+        let volume = volume();
+        let controller = MockController::new();
+        let msc = MassStorage::new(&controller, &volume);
+        msc.enable();
+
+        command(
+            &msc,
+            &controller,
+            6,
+            512,
+            false,
+            &[0x2A, 0, 0, 0, 0, 0, 0, 0, 1, 0],
+        );
+        for _ in 0..8 {
+            assert!(match msc.packet_out(TransferType::Bulk, OUT_ENDPOINT, 64) {
+                OutResult::Ok => true,
+                _ => false,
+            });
+        }
+        status(&msc, &controller, 6, 512, FAILED);
+
+        // DATA PROTECT, WRITE PROTECTED
+        command(&msc, &controller, 7, 18, true, &[0x03, 0, 0, 0, 18, 0]);
+        data_in(
+            &msc,
+            &controller,
+            &[
+                0x70, 0, 0x07, 0, 0, 0, 0, 10, 0, 0, 0, 0, 0x27, 0x00, 0, 0, 0, 0,
+            ],
+        );
+        status(&msc, &controller, 7, 0, PASSED);
+
+        // The sense is cleared once reported
+        command(&msc, &controller, 8, 18, true, &[0x03, 0, 0, 0, 18, 0]);
+        data_in(
+            &msc,
+            &controller,
+            &[0x70, 0, 0, 0, 0, 0, 0, 10, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0],
+        );
+        status(&msc, &controller, 8, 0, PASSED);
+    }
+
+    #[test]
+    pub fn unknown_command() {
+        let volume = volume();
+        let controller = MockController::new();
+        let msc = MassStorage::new(&controller, &volume);
+        msc.enable();
+
+        command(&msc, &controller, 9, 0, false, &[0xFF, 0, 0, 0, 0, 0]);
+        status(&msc, &controller, 9, 0, FAILED);
+
+        // ILLEGAL REQUEST, INVALID COMMAND OPERATION CODE
+        command(&msc, &controller, 10, 14, true, &[0x03, 0, 0, 0, 14, 0]);
+        data_in(
+            &msc,
+            &controller,
+            &[0x70, 0, 0x05, 0, 0, 0, 0, 10, 0, 0, 0, 0, 0x20, 0x00],
+        );
+        status(&msc, &controller, 10, 0, PASSED);
+    }
+
+    #[test]
+    pub fn invalid_cbw_is_ignored() {
+        let log = MockLogger::new();
+        let volume = volume();
+        let controller = MockController::new();
+        let msc = MassStorage::new(&controller, &volume);
+        msc.set_logger(&log);
+        msc.enable();
+
+        // A packet that is too short, then one without the CBW signature
+        assert!(match msc.packet_out(TransferType::Bulk, OUT_ENDPOINT, 30) {
+            OutResult::Ok => true,
+            _ => false,
+        });
+        assert_eq!(log.take(), "msc: invalid CBW of 30 bytes\n");
+        controller.buffer(OUT_ENDPOINT)[0].set(b'X');
+        assert!(match msc.packet_out(TransferType::Bulk, OUT_ENDPOINT, 31) {
+            OutResult::Ok => true,
+            _ => false,
+        });
+        assert_eq!(log.take(), "msc: invalid CBW of 31 bytes\n");
+
+        // Nothing is answered, and the next valid CBW is served
+        assert!(match msc.packet_in(TransferType::Bulk, IN_ENDPOINT) {
+            InResult::Delay => true,
+            _ => false,
+        });
+        command(&msc, &controller, 9, 0, false, &[0x00, 0, 0, 0, 0, 0]);
+        status(&msc, &controller, 9, 0, PASSED);
+
+        // Packets on the wrong endpoint or of the wrong type do not stall
+        assert!(match msc.packet_out(TransferType::Bulk, IN_ENDPOINT, 31) {
+            OutResult::Ok => true,
+            _ => false,
+        });
+        assert!(match msc.packet_in(TransferType::Interrupt, IN_ENDPOINT) {
+            InResult::Delay => true,
+            _ => false,
+        });
+        assert_eq!(
+            log.take(),
+            "msc: packet_out on unknown endpoint 1\nmsc: packet_in(1) is not a bulk transfer\n"
+        );
+    }
+}
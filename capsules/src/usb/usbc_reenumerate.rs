@@ -0,0 +1,216 @@
+//! Forces the host to enumerate a USB device again
+//!
+//! Detaching from the bus looks to the host as if the device was unplugged.
+//! After a delay, long enough for the host to notice, the device attaches
+//! again and the host reads its descriptors afresh. This is needed when the
+//! descriptors change, e.g. after a firmware update or a personality switch.
+//!
+//! The client is put through a bus reset while detached, so it starts again
+//! unconfigured, just as after a real replug.
+//!
+//! Usage
+//! -----
+//!
+//! ```rust
+//! let reenumerate = static_init!(
+//!     capsules::usb::usbc_reenumerate::Reenumerate<
+//!         'static,
+//!         nrf52::usbd::Usbd<'static>,
+//!         VirtualMuxAlarm<'static, nrf5x::rtc::Rtc>,
+//!     >,
+//!     capsules::usb::usbc_reenumerate::Reenumerate::new(&nrf52::usbd::USBD, alarm, client));
+//! alarm.set_client(reenumerate);
+//!
+//! reenumerate.reenumerate(500);
+//! ```
+
+use super::usbc_client_ctrl::{self, Logger};
+use core::cell::Cell;
+use kernel::common::cells::OptionalCell;
+use kernel::hil;
+use kernel::hil::time::{self, Frequency};
+use kernel::ReturnCode;
+
+pub struct Reenumerate<'a, C: 'a, A: 'a> {
+    controller: &'a C,
+    alarm: &'a A,
+    client: &'a dyn hil::usb::Client<'a>,
+
+    // Whether we are detached, waiting for the alarm to attach again
+    detached: Cell<bool>,
+
+    // Given our diagnostics instead of `debug!`
+    logger: OptionalCell<&'a dyn Logger>,
+}
+
+impl<'a, C: hil::usb::UsbController<'a>, A: time::Alarm<'a>> Reenumerate<'a, C, A> {
+    pub fn new(controller: &'a C, alarm: &'a A, client: &'a dyn hil::usb::Client<'a>) -> Self {
+        Reenumerate {
+            controller,
+            alarm,
+            client,
+            detached: Cell::new(false),
+            logger: OptionalCell::empty(),
+        }
+    }
+
+    /// Register a logger to receive our diagnostics instead of `debug!`
+    pub fn set_logger(&self, logger: &'a dyn Logger) {
+        self.logger.set(logger);
+    }
+
+    /// Detach from the bus, and attach again after `delay_ms` milliseconds
+    ///
+    /// Returns `EBUSY` if the device is already waiting to attach again, and
+    /// `EINVAL` if the delay is too long for the alarm to count.  In both
+    /// cases the device stays as it was.
+    pub fn reenumerate(&self, delay_ms: u32) -> ReturnCode {
+        if self.detached.get() {
+            return ReturnCode::EBUSY;
+        }
+
+        // In 64 bits, as long delays on fast alarms overflow 32
+        let interval = delay_ms as u64 * <A::Frequency>::frequency() as u64 / 1000;
+        if interval > core::u32::MAX as u64 {
+            return ReturnCode::EINVAL;
+        }
+        self.detached.set(true);
+
+        self.controller.detach();
+
+        // Whatever the host had set up is gone along with it
+        self.client.bus_reset();
+
+        self.alarm
+            .set_alarm(self.alarm.now().wrapping_add(interval as u32));
+        ReturnCode::SUCCESS
+    }
+
+    /// Whether the device is detached, waiting to attach again
+    pub fn is_detached(&self) -> bool {
+        self.detached.get()
+    }
+}
+
+impl<'a, C: hil::usb::UsbController<'a>, A: time::Alarm<'a>> time::AlarmClient
+    for Reenumerate<'a, C, A>
+{
+    fn fired(&self) {
+        if self.detached.take() {
+            let result = self.client.attach();
+            if result != ReturnCode::SUCCESS {
+                usbc_client_ctrl::log(
+                    &self.logger,
+                    format_args!("reenumerate: attach failed: {:?}", result),
+                );
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::super::mock::{MockAlarm, MockController, MockLogger};
+    use super::super::usbc_hid_consumer::ConsumerControl;
+    use super::Reenumerate;
+    use kernel::hil::time::{Alarm, Freq16MHz};
+    use kernel::hil::usb::{Client, CtrlInResult};
+    use kernel::ReturnCode;
+
+    /// The configuration value reported by GET_CONFIGURATION
+    fn configuration(
+        consumer: &'a ConsumerControl<'a, MockController<'a>>,
+        controller: &MockController<'a>,
+    ) -> u8 {
+        controller.setup([0x80, 0x08, 0x00, 0x00, 0x00, 0x00, 0x01, 0x00]);
+        consumer.ctrl_setup(0);
+        assert!(match consumer.ctrl_in(0) {
+            CtrlInResult::Packet(1, true) => true,
+            _ => false,
+        });
+        consumer.ctrl_status(0);
+        consumer.ctrl_status_complete(0);
+        controller.buffer(0)[0].get()
+    }
+
+    #[test]
+    pub fn detach_delay_attach() {
+        let controller = MockController::new();
+        let alarm = MockAlarm::new();
+        let consumer = ConsumerControl::new(&controller);
+        let reenumerate = Reenumerate::new(&controller, &alarm, &consumer);
+        alarm.set_client(&reenumerate);
+
+        consumer.enable();
+        assert_eq!(consumer.attach(), ReturnCode::SUCCESS);
+        assert!(controller.attached.get());
+
+        // SET_CONFIGURATION(1)
+        controller.setup([0x00, 0x09, 0x01, 0x00, 0x00, 0x00, 0x00, 0x00]);
+        consumer.ctrl_setup(0);
+        consumer.ctrl_status(0);
+        consumer.ctrl_status_complete(0);
+        assert_eq!(configuration(&consumer, &controller), 1);
+
+        alarm.now.set(1000);
+        assert_eq!(reenumerate.reenumerate(200), ReturnCode::SUCCESS);
+        assert!(!controller.attached.get());
+        assert!(reenumerate.is_detached());
+        assert_eq!(configuration(&consumer, &controller), 0);
+        assert_eq!(alarm.alarm.get(), Some(1200));
+
+        // A second request while detached is refused
+        assert_eq!(reenumerate.reenumerate(200), ReturnCode::EBUSY);
+        assert_eq!(alarm.alarm.get(), Some(1200));
+
+        alarm.fire();
+        assert!(controller.attached.get());
+        assert!(!reenumerate.is_detached());
+
+        // A delay whose tic count only fits in 64 bits while it is computed
+        assert_eq!(reenumerate.reenumerate(4_000_000_000), ReturnCode::SUCCESS);
+        assert_eq!(alarm.alarm.get(), Some(1200u32.wrapping_add(4_000_000_000)));
+    }
+
+    #[test]
+    pub fn delay_too_long_for_alarm() {
+        let controller = MockController::new();
+        let alarm: MockAlarm<Freq16MHz> = MockAlarm::with_frequency();
+        let consumer = ConsumerControl::new(&controller);
+        let reenumerate = Reenumerate::new(&controller, &alarm, &consumer);
+        alarm.set_client(&reenumerate);
+
+        consumer.enable();
+        assert_eq!(consumer.attach(), ReturnCode::SUCCESS);
+
+        // 268 436 ms is more than 2^32 tics at 16 MHz, and the device stays attached
+        assert_eq!(reenumerate.reenumerate(268_436), ReturnCode::EINVAL);
+        assert!(controller.attached.get());
+        assert!(!reenumerate.is_detached());
+        assert_eq!(alarm.alarm.get(), None);
+
+        // The longest delay that fits
+        alarm.now.set(1000);
+        assert_eq!(reenumerate.reenumerate(268_435), ReturnCode::SUCCESS);
+        assert!(!controller.attached.get());
+        assert_eq!(alarm.alarm.get(), Some(1000u32.wrapping_add(4_294_960_000)));
+    }
+
+    #[test]
+    pub fn attach_failure_is_logged() {
+        let log = MockLogger::new();
+        let controller = MockController::new();
+        let alarm = MockAlarm::new();
+        let consumer = ConsumerControl::new(&controller);
+        let reenumerate = Reenumerate::new(&controller, &alarm, &consumer);
+        reenumerate.set_logger(&log);
+        alarm.set_client(&reenumerate);
+
+        // The client was never enabled, so it cannot attach again
+        assert_eq!(reenumerate.reenumerate(200), ReturnCode::SUCCESS);
+        alarm.fire();
+        assert!(!controller.attached.get());
+        assert!(!reenumerate.is_detached());
+        assert_eq!(log.take(), "reenumerate: attach failed: EOFF\n");
+    }
+}
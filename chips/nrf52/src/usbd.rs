@@ -324,6 +324,11 @@ mod detail {
             self.ptr.set(slice.as_ptr() as *const u8);
             self.maxcnt.write(Count::MAXCNT.val(slice.len() as u32));
         }
+
+        /// Number of bytes transferred by the last DMA transaction
+        pub fn amount(&self) -> usize {
+            self.amount.read(Amount::AMOUNT) as usize
+        }
     }
 }
 
@@ -1505,8 +1510,9 @@ impl<'a> Usbd<'a> {
                     direction,
                     BulkState::Init,
                 ));
+                let packet_bytes = regs.epin[endpoint].amount();
                 self.client
-                    .map(|client| client.packet_transmitted(endpoint));
+                    .map(|client| client.packet_transmitted(endpoint, packet_bytes));
             }
         }
 
@@ -1,6 +1,7 @@
 //! Interface to USB controller hardware
 
 use crate::common::cells::VolatileCell;
+use crate::returncode::ReturnCode;
 
 /// USB controller interface
 pub trait UsbController<'a> {
@@ -37,6 +38,7 @@ pub enum TransferType {
     Interrupt,
 }
 
+#[derive(Copy, Clone, Debug, PartialEq)]
 pub enum DeviceSpeed {
     Full,
     Low,
@@ -45,7 +47,11 @@ pub enum DeviceSpeed {
 /// USB controller client interface
 pub trait Client<'a> {
     fn enable(&'a self);
-    fn attach(&'a self);
+
+    /// Attach to the bus, once `enable` has set up the controller
+    ///
+    /// Returns `EOFF` if the client has not been enabled.
+    fn attach(&'a self) -> ReturnCode;
     fn bus_reset(&'a self);
 
     fn ctrl_setup(&'a self, endpoint: usize) -> CtrlSetupResult;
@@ -62,7 +68,12 @@ pub trait Client<'a> {
         packet_bytes: u32,
     ) -> OutResult;
 
-    fn packet_transmitted(&'a self, endpoint: usize);
+    /// An IN packet of `packet_bytes` bytes was transmitted to the host
+    ///
+    /// Not every controller reports this: the nRF52 one does, but the SAM4L
+    /// one never calls it, so clients that must also work there cannot rely on
+    /// it.  Clients that have no use for it need not implement it.
+    fn packet_transmitted(&'a self, _endpoint: usize, _packet_bytes: usize) {}
 }
 
 #[derive(Debug)]